@@ -32,12 +32,11 @@
           buildInputs = [ generator ];
 
           buildPhase = ''
-            cd generator
-            ${generator}/bin/nicolaschan-generator
+            ${generator}/bin/nicolaschan-generator build --content content --output dist
           '';
 
           installPhase = ''
-            cp -r $src/dist $out || cp -r ../dist $out
+            cp -r dist $out
           '';
         };
       in
@@ -51,13 +50,12 @@
           buildInputs = [
             rust
             pkgs.rust-analyzer
-            pkgs.simple-http-server
           ];
 
           RUST_SRC_PATH = "${rust}/lib/rustlib/src/rust/library";
 
           shellHook = ''
-            alias serve="simple-http-server -p 8000 -i ../dist"
+            alias serve="cargo run -- serve"
           '';
         };
       }
@@ -0,0 +1,134 @@
+use crate::post::Post;
+use chrono::{Datelike, NaiveDate};
+use std::cmp::Reverse;
+
+/// Every post by year and month, newest first, for `/archive/` and the
+/// `/YYYY/` pages
+pub struct Archive<'a> {
+    pub years: Vec<Year<'a>>,
+    /// Posts without a `date`, listed after the dated ones
+    pub undated: Vec<&'a Post>,
+}
+
+pub struct Year<'a> {
+    pub year: i32,
+    pub months: Vec<Month<'a>>,
+}
+
+pub struct Month<'a> {
+    /// First day of the month
+    pub start: NaiveDate,
+    pub posts: Vec<&'a Post>,
+}
+
+impl Year<'_> {
+    /// Site-relative URL of the year's page
+    pub fn url_path(&self) -> String {
+        format!("{}/", self.year)
+    }
+
+    pub fn count(&self) -> usize {
+        self.months.iter().map(|month| month.posts.len()).sum()
+    }
+
+    pub fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.months
+            .iter()
+            .flat_map(|month| month.posts.iter().copied())
+    }
+}
+
+impl Month<'_> {
+    /// Month name, e.g. "March"
+    pub fn name(&self) -> String {
+        self.start.format("%B").to_string()
+    }
+
+    /// Fragment id of the month's heading, e.g. `2024-03`
+    pub fn anchor(&self) -> String {
+        self.start.format("%Y-%m").to_string()
+    }
+}
+
+/// Groups posts by the year and month of their `date`. Posts are expected
+/// newest first, as loaded, and keep that order within a month.
+pub fn collect_archive(posts: &[Post]) -> Archive<'_> {
+    let mut years: Vec<Year> = Vec::new();
+    let mut undated = Vec::new();
+
+    for post in posts {
+        let Some(date) = post.date.map(|d| d.date_naive()) else {
+            undated.push(post);
+            continue;
+        };
+        let start = date.with_day(1).unwrap_or(date);
+        let year = match years.iter_mut().find(|y| y.year == date.year()) {
+            Some(year) => year,
+            None => {
+                years.push(Year {
+                    year: date.year(),
+                    months: Vec::new(),
+                });
+                years.last_mut().unwrap()
+            }
+        };
+        match year.months.iter_mut().find(|m| m.start == start) {
+            Some(month) => month.posts.push(post),
+            None => year.months.push(Month {
+                start,
+                posts: vec![post],
+            }),
+        }
+    }
+
+    years.sort_by_key(|year| Reverse(year.year));
+    for year in &mut years {
+        year.months.sort_by_key(|month| Reverse(month.start));
+    }
+    Archive { years, undated }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_archive_groups_by_year_and_month() {
+        let posts = vec![
+            Post::for_test("d", "date: 2024-03-20", "Body"),
+            Post::for_test("c", "date: 2024-03-02", "Body"),
+            Post::for_test("b", "date: 2024-01-15T23:30:00-08:00", "Body"),
+            Post::for_test("a", "date: 2022-09-04", "Body"),
+            Post::for_test("undated", "", "Body"),
+        ];
+        let archive = collect_archive(&posts);
+
+        let outline: Vec<(i32, Vec<(String, usize)>)> = archive
+            .years
+            .iter()
+            .map(|y| {
+                (
+                    y.year,
+                    y.months
+                        .iter()
+                        .map(|m| (m.anchor(), m.posts.len()))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outline,
+            vec![
+                (
+                    2024,
+                    vec![("2024-03".to_string(), 2), ("2024-01".to_string(), 1)]
+                ),
+                (2022, vec![("2022-09".to_string(), 1)]),
+            ]
+        );
+        assert_eq!(archive.years[0].url_path(), "2024/");
+        assert_eq!(archive.years[0].count(), 3);
+        assert_eq!(archive.years[0].months[0].name(), "March");
+        assert_eq!(archive.undated.len(), 1);
+    }
+}
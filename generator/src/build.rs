@@ -0,0 +1,513 @@
+use crate::archive;
+use crate::cache::{hash_bytes, hash_dir, BuildCache, GENERATOR_HASH};
+use crate::card::{self, Cards};
+use crate::config::SiteConfig;
+use crate::error::{Error, ErrorKind};
+use crate::highlight;
+use crate::images::Images;
+use crate::markdown;
+use crate::page::Page;
+use crate::paginate::paginate;
+use crate::post::Post;
+use crate::related;
+use crate::search;
+use crate::series::{self, Series};
+use crate::sitemap::{self, Sitemap};
+use crate::tags;
+use crate::template;
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Where content is read from and output is written to
+#[derive(Debug, Clone)]
+pub struct SitePaths {
+    pub content_root: PathBuf,
+    pub output_dir: PathBuf,
+    /// Where the incremental build cache lives; `None` forces a full rebuild
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl SitePaths {
+    pub fn posts_dir(&self) -> PathBuf {
+        self.content_root.join("posts")
+    }
+
+    pub fn pages_dir(&self) -> PathBuf {
+        self.content_root.join("pages")
+    }
+
+    pub fn resources_dir(&self) -> PathBuf {
+        self.content_root.join("resources")
+    }
+
+    /// Custom `.sublime-syntax` and `.tmTheme` files for code blocks
+    pub fn highlighting_dir(&self) -> PathBuf {
+        self.content_root.join("highlighting")
+    }
+}
+
+/// Which unpublished content to include in a build
+#[derive(Debug, Clone, Copy)]
+pub struct BuildOptions {
+    /// Include posts and pages marked `draft: true`
+    pub drafts: bool,
+    /// Include posts whose publication date is after `today`
+    pub future: bool,
+    pub today: NaiveDate,
+    /// Skip files that fail to parse instead of failing the build
+    pub keep_going: bool,
+}
+
+impl BuildOptions {
+    /// Whether a post should be published by this build
+    pub fn includes(&self, post: &Post) -> bool {
+        (self.drafts || !post.draft) && (self.future || !post.is_scheduled_after(self.today))
+    }
+
+    /// Whether a page should be published by this build
+    pub fn includes_page(&self, page: &Page) -> bool {
+        self.drafts || !page.draft
+    }
+}
+
+/// Parsed site content, plus any files that failed to parse
+pub struct Content {
+    /// Posts sorted by date, newest first (undated posts last)
+    pub posts: Vec<Post>,
+    pub pages: Vec<Page>,
+    pub errors: Vec<Error>,
+    /// Markdown file each post and page was parsed from, by site-relative URL
+    pub sources: HashMap<String, PathBuf>,
+}
+
+/// Outcome of a build that ran to completion
+pub struct BuildReport {
+    pub posts: usize,
+    /// Outputs left as they were because nothing they depend on changed
+    pub unchanged: usize,
+    /// Files left out of the site because of `--keep-going`
+    pub skipped: Vec<Error>,
+    /// Problems worth fixing that didn't stop the build
+    pub warnings: Vec<Error>,
+    /// Markdown file each rendered post and page came from, by site-relative URL
+    pub sources: HashMap<String, PathBuf>,
+}
+
+/// Parses every markdown file under a directory, collecting errors instead
+/// of stopping at the first one. Files whose hash matches the build cache
+/// are not parsed again. Items are returned with the file they came from.
+fn load_markdown<T: Serialize + DeserializeOwned>(
+    dir: &Path,
+    parse: impl Fn(&str, &str) -> Result<T, Error>,
+    mut cache: Option<&mut BuildCache>,
+    errors: &mut Vec<Error>,
+) -> Vec<(PathBuf, T)> {
+    let mut items = Vec::new();
+    if !dir.exists() {
+        return items;
+    }
+
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "md"))
+    {
+        let content = match fs::read_to_string(entry.path()) {
+            Ok(content) => content,
+            Err(e) => {
+                errors.push(Error::io(entry.path(), e));
+                continue;
+            }
+        };
+        let hash = hash_bytes(content.as_bytes());
+        if let Some(item) = cache.as_deref().and_then(|c| c.cached_source(entry.path(), &hash)) {
+            if let Some(cache) = cache.as_deref_mut() {
+                cache.record_source(entry.path(), hash, &item);
+            }
+            items.push((entry.into_path(), item));
+            continue;
+        }
+
+        let filename = entry.path().file_name().and_then(|n| n.to_str()).unwrap_or("");
+        match parse(&content, filename) {
+            Ok(item) => {
+                if let Some(cache) = cache.as_deref_mut() {
+                    cache.record_source(entry.path(), hash, &item);
+                }
+                items.push((entry.into_path(), item));
+            }
+            Err(e) => errors.push(e.with_path(entry.path())),
+        }
+    }
+
+    items
+}
+
+/// Reads and parses all posts and pages, reusing unchanged ones from the
+/// cache. `strict` rejects unknown frontmatter keys.
+fn load_content(paths: &SitePaths, strict: bool, mut cache: Option<&mut BuildCache>) -> Content {
+    let mut errors = Vec::new();
+    let mut sources = HashMap::new();
+    let mut posts: Vec<Post> = load_markdown(
+        &paths.posts_dir(),
+        |content, filename| Post::from_markdown(content, filename, strict),
+        cache.as_deref_mut(),
+        &mut errors,
+    )
+    .into_iter()
+    .map(|(path, post)| {
+        sources.insert(post.url_path(), path);
+        post
+    })
+    .collect();
+    let parse_page = |content: &str, filename: &str| Page::from_markdown(content, filename, strict);
+    let pages = load_markdown(&paths.pages_dir(), parse_page, cache, &mut errors)
+        .into_iter()
+        .map(|(path, page)| {
+            sources.insert(page.url_path(), path);
+            page
+        })
+        .collect();
+
+    // Sort posts by date (newest first), only posts with dates
+    posts.sort_by_key(|post| std::cmp::Reverse(post.date));
+
+    Content {
+        posts,
+        pages,
+        errors,
+        sources,
+    }
+}
+
+/// Moves posts whose `author` isn't in the site config from the content
+/// into its errors
+fn check_authors(site: &SiteConfig, content: &mut Content) {
+    let (posts, unknown): (Vec<Post>, Vec<Post>) = std::mem::take(&mut content.posts)
+        .into_iter()
+        .partition(|post| post.author.as_deref().is_none_or(|id| site.find_author(id).is_some()));
+    content.posts = posts;
+
+    for post in unknown {
+        let message = format!(
+            "unknown author `{}`; authors are listed under `[[authors]]` in the site config",
+            post.author.as_deref().unwrap_or_default()
+        );
+        let mut error = Error::new(ErrorKind::Author, message);
+        if let Some(path) = content.sources.remove(&post.url_path()) {
+            let source = fs::read_to_string(&path).unwrap_or_default();
+            if let Some(offset) = markdown::value_offset(&source, "author") {
+                error = error.at_offset(&source, offset);
+            }
+            error = error.with_path(&path);
+        }
+        content.errors.push(error);
+    }
+}
+
+/// Error for a page whose URL is already used by a generated page such as
+/// `archive/`, pointing at the page's `slug` if it sets one
+fn url_conflict(page: &Page, sources: &HashMap<String, PathBuf>) -> Error {
+    let url = page.url_path();
+    let generated = match url.split('/').next().unwrap_or_default() {
+        "archive" => "the archive".to_string(),
+        "search" => "the search page".to_string(),
+        "tags" => "a tag page".to_string(),
+        "series" => "a series page".to_string(),
+        "page" => "an index page".to_string(),
+        year => format!("the archive for {}", year),
+    };
+    let message = format!(
+        "`/{}` would overwrite {}; give the page another `slug`",
+        url, generated
+    );
+    let mut error = Error::new(ErrorKind::Conflict, message);
+    if let Some(path) = sources.get(&page.url_path()) {
+        let source = fs::read_to_string(path).unwrap_or_default();
+        if let Some(offset) = markdown::value_offset(&source, "slug") {
+            error = error.at_offset(&source, offset);
+        }
+        error = error.with_path(path);
+    }
+    error
+}
+
+/// Copies resources (images, etc.) into the output directory, skipping
+/// files whose size and hash match the previous build
+fn copy_resources(paths: &SitePaths, cache: &mut BuildCache) -> Result<(), Error> {
+    let resources_dir = paths.resources_dir();
+    if !resources_dir.exists() {
+        return Ok(());
+    }
+
+    for entry in WalkDir::new(&resources_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+    {
+        let relative_path = entry.path().strip_prefix(&resources_dir).unwrap();
+        let dest_path = paths.output_dir.join("resources").join(relative_path);
+
+        let bytes = fs::read(entry.path()).map_err(|e| Error::io(entry.path(), e))?;
+        let hash = hash_bytes(&bytes);
+        cache.record_output(&dest_path, &hash);
+        if cache.resource_unchanged(relative_path, bytes.len() as u64, &hash, &dest_path) {
+            continue;
+        }
+
+        write_file(&dest_path, &bytes)?;
+        println!("Copied: {}", dest_path.display());
+    }
+
+    Ok(())
+}
+
+/// Writes a file, creating its directory first
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    fs::write(path, contents).map_err(|e| Error::io(path, e))
+}
+
+/// Writes a rendered file unless the same content is already on disk.
+/// Returns whether the file was written.
+fn write_output(cache: &mut BuildCache, path: &Path, contents: String) -> Result<bool, Error> {
+    let fingerprint = hash_bytes(contents.as_bytes());
+    if cache.is_fresh(path, &fingerprint) {
+        return Ok(false);
+    }
+    write_file(path, contents.as_bytes())?;
+    println!("Generated: {}", path.display());
+    cache.record_output(path, &fingerprint);
+    Ok(true)
+}
+
+/// Renders the whole site into the output directory. Content errors fail
+/// the build before anything is rendered, unless `keep_going` is set, in
+/// which case the broken files are skipped and reported in the result.
+pub fn build(
+    site: &SiteConfig,
+    paths: &SitePaths,
+    options: &BuildOptions,
+) -> Result<BuildReport, Vec<Error>> {
+    let output_dir = &paths.output_dir;
+
+    // Renders are only reusable when produced by this exact binary, config,
+    // choice of unpublished content and custom highlighting syntaxes. The
+    // date isn't part of it: when a scheduled post goes live, the posts
+    // published change, and so do the fingerprints of the pages listing them.
+    let cache_dir = paths.cache_dir.as_deref().filter(|_| !GENERATOR_HASH.is_empty());
+    let highlighting = hash_dir(&paths.highlighting_dir());
+    let key = hash_bytes(
+        format!(
+            "{}{:?}{}{}{}",
+            *GENERATOR_HASH, site, options.drafts, options.future, highlighting
+        )
+        .as_bytes(),
+    );
+    let mut cache = BuildCache::load(cache_dir, output_dir, key);
+
+    // Drafts and scheduled posts are left out of every page, feed and tag
+    // list unless requested
+    highlight::load_syntaxes(&paths.highlighting_dir()).map_err(|e| vec![e])?;
+    let mut content = load_content(paths, site.strict_frontmatter, Some(&mut cache));
+    check_authors(site, &mut content);
+    if !content.errors.is_empty() && !options.keep_going {
+        return Err(content.errors);
+    }
+    content.posts.retain(|post| options.includes(post));
+    content.pages.retain(|page| options.includes_page(page));
+
+    let warnings = series::check(&series::collect_series(&content.posts), &content.sources);
+    let unchanged =
+        render_site(site, paths, options.today, &mut content, &mut cache).map_err(|e| vec![e])?;
+
+    // Remove outputs whose source was deleted or renamed
+    for path in cache.finish() {
+        println!("Removed: {}", path.display());
+    }
+
+    Ok(BuildReport {
+        posts: content.posts.len(),
+        unchanged,
+        skipped: content.errors,
+        warnings,
+        sources: content.sources,
+    })
+}
+
+/// Writes every output file, returning how many were already up to date
+fn render_site(
+    site: &SiteConfig,
+    paths: &SitePaths,
+    today: NaiveDate,
+    content: &mut Content,
+    cache: &mut BuildCache,
+) -> Result<usize, Error> {
+    let output_dir = &paths.output_dir;
+    let mut written = 0;
+    let mut total = 0;
+
+    copy_resources(paths, cache)?;
+
+    // Swap local images for resized variants in modern formats. This runs
+    // before anything is rendered so post fingerprints see the new markup.
+    let mut images = Images::new(&site.images, paths, cache);
+    for post in &mut content.posts {
+        let page_path = post.url_path();
+        post.content_html = images.rewrite_html(&post.content_html, &page_path)?;
+        if let Some(cover_image) = &post.cover_image {
+            post.cover = images.picture(cover_image, &page_path)?;
+        }
+    }
+    for page in &mut content.pages {
+        let page_path = page.url_path();
+        page.content_html = images.rewrite_html(&page.content_html, &page_path)?;
+        if let Some(cover_image) = &page.cover_image {
+            page.cover = images.picture(cover_image, &page_path)?;
+        }
+    }
+    total += images.generated + images.reused;
+    written += images.generated;
+
+    // Draw link preview cards for posts without a cover image
+    if site.cards.enabled {
+        let mut cards = Cards::new(site, paths, cache)?;
+        for post in content.posts.iter().filter(|post| card::wants_card(&site.cards, post)) {
+            cards.render(post)?;
+        }
+        total += cards.generated + cards.reused;
+        written += cards.generated;
+    }
+
+    let posts = &content.posts;
+    let mut sitemap = Sitemap::default();
+    let mut outputs = Vec::new();
+
+    // Generate the index, split into pages at /page/2/ and so on
+    let per_page = site.pagination.per_page;
+    let archive = archive::collect_archive(posts);
+    for pager in paginate(posts, per_page, "") {
+        let index_path = output_dir.join(pager.url_path()).join("index.html");
+        outputs.push((index_path, template::render_index(site, &pager, &archive.years)));
+        sitemap.add(pager.url_path(), sitemap::newest(pager.items));
+    }
+
+    // Generate individual post HTML in slug/index.html for clean URLs.
+    // A post page only depends on the post itself, the parts of its series
+    // and the posts it links to below the article (plus, with `future`,
+    // whether it is still scheduled), so it is skipped entirely when none
+    // of them has changed.
+    let all_series = series::collect_series(posts);
+    let all_related = related::collect_related(posts, site.related.count);
+    for (post, related) in posts.iter().zip(&all_related) {
+        let post_path = output_dir.join("posts").join(&post.slug).join("index.html");
+        let serialized = serde_json::to_string(post)
+            .map_err(|e| Error::new(ErrorKind::Io, e.to_string()).with_path(&post_path))?;
+        let post_series = all_series.iter().find(|s| s.parts.iter().any(|p| std::ptr::eq(*p, post)));
+        let series_fingerprint = post_series.map(Series::fingerprint).unwrap_or_default();
+        let scheduled = post.is_scheduled_after(today).to_string();
+        let fingerprint = cache.fingerprint(&[
+            &serialized,
+            &series_fingerprint,
+            &related.fingerprint(),
+            &scheduled,
+        ]);
+        // Cross-posts are indexed at their canonical URL instead
+        if post.canonical_url.is_none() {
+            sitemap.add(post.url_path(), post.last_modified());
+        }
+        total += 1;
+        if cache.is_fresh(&post_path, &fingerprint) {
+            continue;
+        }
+        let html = template::render_post(site, post, post_series, related, today).map_err(|e| Error::template(&post_path, e))?;
+        write_file(&post_path, html.as_bytes())?;
+        println!("Generated: {}", post_path.display());
+        cache.record_output(&post_path, &fingerprint);
+        written += 1;
+    }
+
+    // Generate Atom and RSS feeds (undated posts are left out)
+    outputs.push((output_dir.join("feed.xml"), template::render_atom(site, posts)));
+    outputs.push((output_dir.join("rss.xml"), template::render_rss(site, posts)));
+
+    // Generate tag index and per-tag archive pages
+    let tags = tags::collect_tags(posts);
+    let tags_dir = output_dir.join("tags");
+    outputs.push((tags_dir.join("index.html"), template::render_tags(site, &tags)));
+    sitemap.add("tags/".to_string(), sitemap::newest(posts));
+    for tag in &tags {
+        for pager in paginate(&tag.posts, per_page, &format!("tags/{}/", tag.slug)) {
+            let tag_path = output_dir.join(pager.url_path()).join("index.html");
+            outputs.push((tag_path, template::render_tag(site, tag, &pager)));
+            sitemap.add(pager.url_path(), sitemap::newest(pager.items.iter().copied()));
+        }
+    }
+
+    // Generate a landing page for each series
+    for s in &all_series {
+        let series_path = output_dir.join("series").join(&s.slug).join("index.html");
+        outputs.push((series_path, template::render_series(site, s)));
+        sitemap.add(format!("series/{}/", s.slug), sitemap::newest(s.parts.iter().copied()));
+    }
+
+    // Generate the archive of every post by month, and a page per year
+    outputs.push((output_dir.join("archive/index.html"), template::render_archive(site, &archive)));
+    sitemap.add("archive/".to_string(), sitemap::newest(posts));
+    for (i, year) in archive.years.iter().enumerate() {
+        let year_path = output_dir.join(year.url_path()).join("index.html");
+        outputs.push((year_path, template::render_year(site, &archive.years, i)));
+        sitemap.add(year.url_path(), sitemap::newest(year.posts()));
+    }
+
+    // Generate the search page and the index it queries. The page has no
+    // content of its own, so it stays out of the sitemap.
+    outputs.push((output_dir.join("search/index.html"), template::render_search(site)));
+    let search_index_path = output_dir.join(search::INDEX_FILE);
+    let search_index = serde_json::to_string(&search::build_index(posts))
+        .map_err(|e| Error::new(ErrorKind::Io, e.to_string()).with_path(&search_index_path))?;
+    total += 1;
+    if write_output(cache, &search_index_path, search_index)? {
+        written += 1;
+    }
+
+    // Generate pages in slug/index.html for clean URLs, unless that would
+    // overwrite one of the pages generated above
+    for page in &content.pages {
+        let page_path = output_dir.join(&page.slug).join("index.html");
+        if outputs.iter().any(|(path, _)| *path == page_path) {
+            return Err(url_conflict(page, &content.sources));
+        }
+        outputs.push((page_path, template::render_page(site, page)));
+        sitemap.add(page.url_path(), page.updated.map(|d| d.date_naive()));
+    }
+
+    // Code highlighting colors for the light and dark themes
+    let highlight_css = highlight::stylesheet(&site.highlight, &paths.highlighting_dir())?;
+    let highlight_path = output_dir.join("highlight.css");
+    total += 1;
+    if write_output(cache, &highlight_path, highlight_css)? {
+        written += 1;
+    }
+
+    // Discovery files for search engines
+    outputs.push((output_dir.join("sitemap.xml"), template::render_sitemap(site, &sitemap)));
+    outputs.push((output_dir.join("robots.txt"), template::render_robots(site)));
+
+    for (path, rendered) in outputs {
+        let contents = rendered.map_err(|e| Error::template(&path, e))?;
+        total += 1;
+        if write_output(cache, &path, contents)? {
+            written += 1;
+        }
+    }
+
+    Ok(total - written)
+}
@@ -0,0 +1,246 @@
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Hex-encoded content hash used for every cache key
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+/// Combined hash of every file under `dir` and its relative path; empty if
+/// the directory doesn't exist
+pub fn hash_dir(dir: &Path) -> String {
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    if files.is_empty() {
+        return String::new();
+    }
+    files.sort();
+
+    let mut hasher = blake3::Hasher::new();
+    for path in files {
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update(&fs::read(&path).unwrap_or_default());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Hash of the running generator binary. Templates are compiled in, so this
+/// changes whenever a template (or the rendering code) does.
+pub static GENERATOR_HASH: Lazy<String> = Lazy::new(|| {
+    std::env::current_exe()
+        .and_then(fs::read)
+        .map(|bytes| hash_bytes(&bytes))
+        .unwrap_or_default()
+});
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    /// Generator and config hash the cached renders were produced with
+    key: String,
+    /// Parsed posts and pages by source path, with the hash they were parsed from
+    sources: BTreeMap<PathBuf, CachedSource>,
+    /// Copied resources by path relative to the resources directory
+    resources: BTreeMap<PathBuf, CachedResource>,
+    /// Fingerprint of each rendered file, by path relative to the output directory
+    outputs: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedSource {
+    hash: String,
+    /// The parsed post or page
+    parsed: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct CachedResource {
+    size: u64,
+    hash: String,
+}
+
+/// Remembers what the previous build produced so unchanged work can be
+/// skipped and outputs whose source disappeared can be removed
+pub struct BuildCache {
+    dir: Option<PathBuf>,
+    output_dir: PathBuf,
+    previous: Manifest,
+    current: Manifest,
+    /// Whether renders from the previous build can be reused
+    key_matches: bool,
+}
+
+impl BuildCache {
+    /// Loads the manifest from `dir`. With no cache dir, or a missing or
+    /// unreadable manifest, everything is rebuilt from scratch.
+    pub fn load(dir: Option<&Path>, output_dir: &Path, key: String) -> Self {
+        let previous: Manifest = dir
+            .and_then(|dir| fs::read(dir.join(MANIFEST_FILE)).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let key_matches = !previous.key.is_empty() && previous.key == key;
+
+        BuildCache {
+            dir: dir.map(Path::to_path_buf),
+            output_dir: output_dir.to_path_buf(),
+            previous,
+            current: Manifest {
+                key,
+                ..Manifest::default()
+            },
+            key_matches,
+        }
+    }
+
+    /// A previously parsed post or page, if its source is unchanged
+    pub fn cached_source<T: DeserializeOwned>(&self, source: &Path, hash: &str) -> Option<T> {
+        if !self.key_matches {
+            return None;
+        }
+        let cached = self.previous.sources.get(source)?;
+        if cached.hash != hash {
+            return None;
+        }
+        serde_json::from_value(cached.parsed.clone()).ok()
+    }
+
+    pub fn record_source<T: Serialize>(&mut self, source: &Path, hash: String, parsed: &T) {
+        if let Ok(parsed) = serde_json::to_value(parsed) {
+            self.current
+                .sources
+                .insert(source.to_path_buf(), CachedSource { hash, parsed });
+        }
+    }
+
+    /// Whether a resource was already copied with the same size and hash.
+    /// Resources don't depend on the generator, so this ignores the cache key.
+    pub fn resource_unchanged(
+        &mut self,
+        relative: &Path,
+        size: u64,
+        hash: &str,
+        dest: &Path,
+    ) -> bool {
+        let entry = CachedResource {
+            size,
+            hash: hash.to_string(),
+        };
+        let unchanged = dest.is_file()
+            && dest.metadata().map(|m| m.len()).ok() == Some(size)
+            && self.previous.resources.get(relative) == Some(&entry);
+        self.current.resources.insert(relative.to_path_buf(), entry);
+        unchanged
+    }
+
+    /// Fingerprint for an output derived from the given inputs, mixed with
+    /// the cache key so a generator or config change invalidates it
+    pub fn fingerprint(&self, inputs: &[&str]) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.current.key.as_bytes());
+        for input in inputs {
+            hasher.update(&(input.len() as u64).to_le_bytes());
+            hasher.update(input.as_bytes());
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Whether `path` was produced with the same `fingerprint` last time and
+    /// is still on disk. Fresh outputs are kept and need not be rewritten.
+    pub fn is_fresh(&mut self, path: &Path, fingerprint: &str) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.output_dir) else {
+            return false;
+        };
+        let fresh = path.is_file()
+            && self.previous.outputs.get(relative).map(String::as_str) == Some(fingerprint);
+        if fresh {
+            self.record_output(path, fingerprint);
+        }
+        fresh
+    }
+
+    /// Records an output written by this build along with its fingerprint
+    pub fn record_output(&mut self, path: &Path, fingerprint: &str) {
+        if let Ok(relative) = path.strip_prefix(&self.output_dir) {
+            self.current
+                .outputs
+                .insert(relative.to_path_buf(), fingerprint.to_string());
+        }
+    }
+
+    /// Deletes outputs from the previous build that this build didn't
+    /// produce, then saves the manifest. Returns the removed paths.
+    pub fn finish(self) -> Vec<PathBuf> {
+        let mut removed = Vec::new();
+        let mut dirs = BTreeSet::new();
+        for relative in self.previous.outputs.keys() {
+            if self.current.outputs.contains_key(relative) {
+                continue;
+            }
+            let path = self.output_dir.join(relative);
+            if fs::remove_file(&path).is_ok() {
+                removed.push(path.clone());
+                dirs.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+            }
+        }
+
+        // Deepest first so nested empty directories collapse upwards
+        for dir in dirs.iter().rev() {
+            if dir.starts_with(&self.output_dir) && dir != &self.output_dir {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+
+        if let Some(dir) = &self.dir {
+            let saved = fs::create_dir_all(dir).and_then(|()| {
+                let json = serde_json::to_vec(&self.current).map_err(std::io::Error::other)?;
+                fs::write(dir.join(MANIFEST_FILE), json)
+            });
+            if let Err(e) = saved {
+                eprintln!("Warning: failed to save build cache: {}", e);
+            }
+        }
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_outputs_are_removed() {
+        let root = std::env::temp_dir().join(format!("generator-cache-{}", std::process::id()));
+        let output_dir = root.join("dist");
+        let cache_dir = root.join("cache");
+        let kept = output_dir.join("kept.html");
+        let stale = output_dir.join("posts/gone/index.html");
+        fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        fs::write(&kept, "kept").unwrap();
+        fs::write(&stale, "stale").unwrap();
+
+        let mut cache = BuildCache::load(Some(&cache_dir), &output_dir, "key".to_string());
+        cache.record_output(&kept, "a");
+        cache.record_output(&stale, "b");
+        cache.finish();
+
+        let mut cache = BuildCache::load(Some(&cache_dir), &output_dir, "key".to_string());
+        assert!(cache.is_fresh(&kept, "a"));
+        let removed = cache.finish();
+
+        assert_eq!(removed, vec![stale.clone()]);
+        assert!(kept.exists());
+        assert!(!output_dir.join("posts").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+//! Link preview cards. Posts without a cover image get a 1200×630 PNG with
+//! their title, date, reading time and tags, so shared links still show a
+//! large preview. Fonts are bundled so cards look the same on any machine.
+
+use crate::build::{write_file, SitePaths};
+use crate::cache::{hash_bytes, BuildCache};
+use crate::config::{CardAlign, CardConfig, Color, SiteConfig};
+use crate::error::{Error, ErrorKind};
+use crate::post::Post;
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use image::{ImageFormat, Rgb, RgbImage};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Written next to the post's `index.html`
+pub const FILE_NAME: &str = "og.png";
+
+/// The size link previews are displayed at on most sites
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+
+const PADDING: f32 = 80.0;
+/// Thickness of the accent bar along the bottom edge
+const BAR: u32 = 12;
+const SITE_NAME_SIZE: f32 = 32.0;
+const DETAILS_SIZE: f32 = 28.0;
+/// Title sizes to try, largest first, until the title fits
+const TITLE_SIZES: [f32; 5] = [72.0, 64.0, 56.0, 48.0, 44.0];
+const LINE_HEIGHT: f32 = 1.2;
+
+static TITLE_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+static TEXT_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+/// Whether a post gets a generated card: only when it has no image of its
+/// own to preview
+pub fn wants_card(config: &CardConfig, post: &Post) -> bool {
+    config.enabled && post.meta.image.is_none() && post.cover_image.is_none()
+}
+
+/// Draws and writes cards, skipping those whose text and style are unchanged
+pub struct Cards<'a> {
+    site: &'a SiteConfig,
+    title_font: FontArc,
+    text_font: FontArc,
+    /// Hash of the font files, so replacing a font redraws every card
+    fonts_hash: String,
+    output_dir: PathBuf,
+    cache: &'a mut BuildCache,
+    /// Cards drawn by this build
+    pub generated: usize,
+    /// Cards left as they were because nothing on them changed
+    pub reused: usize,
+}
+
+impl<'a> Cards<'a> {
+    pub fn new(
+        site: &'a SiteConfig,
+        paths: &SitePaths,
+        cache: &'a mut BuildCache,
+    ) -> Result<Self, Error> {
+        let config = &site.cards;
+        let title_bytes = font_bytes(config.title_font.as_deref(), TITLE_FONT, paths)?;
+        let text_bytes = font_bytes(config.text_font.as_deref(), TEXT_FONT, paths)?;
+        let fonts_hash = hash_bytes(&[title_bytes.as_slice(), text_bytes.as_slice()].concat());
+        let title_font = load_font(title_bytes, config.title_font.as_deref(), paths)?;
+        let text_font = load_font(text_bytes, config.text_font.as_deref(), paths)?;
+        Ok(Cards {
+            site,
+            title_font,
+            text_font,
+            fonts_hash,
+            output_dir: paths.output_dir.clone(),
+            cache,
+            generated: 0,
+            reused: 0,
+        })
+    }
+
+    /// Writes the card for `post` to `posts/<slug>/og.png`
+    pub fn render(&mut self, post: &Post) -> Result<(), Error> {
+        let path = self.output_dir.join(post.url_path()).join(FILE_NAME);
+        // Hashed without the cache key, like image variants, so rebuilding
+        // the generator doesn't redraw every card
+        let fingerprint = hash_bytes(
+            format!(
+                "{}\n{}\n{}\n{}\n{}\n{:?}\n{}",
+                self.site.name,
+                post.title,
+                post.formatted_date(),
+                post.formatted_reading_time(),
+                post.tags.join("\t"),
+                self.site.cards,
+                self.fonts_hash
+            )
+            .as_bytes(),
+        );
+        if self.cache.is_fresh(&path, &fingerprint) {
+            self.reused += 1;
+            return Ok(());
+        }
+
+        let card = self.draw(post);
+        let mut encoded = Cursor::new(Vec::new());
+        card.write_to(&mut encoded, ImageFormat::Png).map_err(|e| {
+            Error::new(ErrorKind::Image, format!("can't encode card: {}", e)).with_path(&path)
+        })?;
+        write_file(&path, encoded.get_ref())?;
+        println!("Generated: {}", path.display());
+        self.cache.record_output(&path, &fingerprint);
+        self.generated += 1;
+        Ok(())
+    }
+
+    fn draw(&self, post: &Post) -> RgbImage {
+        let config = &self.site.cards;
+        let mut card = RgbImage::from_pixel(WIDTH, HEIGHT, Rgb(config.background.0));
+        for y in HEIGHT - BAR..HEIGHT {
+            for x in 0..WIDTH {
+                card.put_pixel(x, y, Rgb(config.accent.0));
+            }
+        }
+        let mut canvas = Canvas {
+            image: &mut card,
+            align: config.align,
+        };
+        let max_width = WIDTH as f32 - 2.0 * PADDING;
+
+        // Site name at the top
+        let mut baseline = PADDING + SITE_NAME_SIZE;
+        canvas.text(
+            &self.text_font,
+            SITE_NAME_SIZE,
+            config.accent,
+            baseline,
+            &self.site.name,
+        );
+
+        // Details along the bottom: date and reading time, then tags
+        let mut details = vec![post.formatted_reading_time()];
+        if post.has_date() {
+            details.insert(0, post.formatted_date());
+        }
+        let mut footer = vec![details.join(" · ")];
+        if !post.tags.is_empty() {
+            let tags: Vec<String> = post.tags.iter().map(|tag| format!("#{}", tag)).collect();
+            footer.push(tags.join("  "));
+        }
+        let footer_top = HEIGHT as f32 - PADDING - footer.len() as f32 * DETAILS_SIZE * LINE_HEIGHT;
+        let mut footer_baseline = footer_top + DETAILS_SIZE;
+        for line in &footer {
+            let line = truncate(&self.text_font, DETAILS_SIZE, line, max_width);
+            canvas.text(
+                &self.text_font,
+                DETAILS_SIZE,
+                config.muted,
+                footer_baseline,
+                &line,
+            );
+            footer_baseline += DETAILS_SIZE * LINE_HEIGHT;
+        }
+
+        // The title fills the space in between at the largest size that fits
+        let top = baseline + SITE_NAME_SIZE;
+        let available = footer_top - top - SITE_NAME_SIZE;
+        let (size, lines) = fit_title(&self.title_font, &post.title, max_width, available);
+        baseline = top + size;
+        for line in &lines {
+            canvas.text(&self.title_font, size, config.text, baseline, line);
+            baseline += size * LINE_HEIGHT;
+        }
+        card
+    }
+}
+
+/// The configured font file, or else the bundled one
+fn font_bytes(
+    configured: Option<&Path>,
+    bundled: &[u8],
+    paths: &SitePaths,
+) -> Result<Vec<u8>, Error> {
+    match configured {
+        Some(path) => {
+            let path = paths.content_root.join(path);
+            fs::read(&path).map_err(|e| Error::io(&path, e))
+        }
+        None => Ok(bundled.to_vec()),
+    }
+}
+
+fn load_font(
+    bytes: Vec<u8>,
+    configured: Option<&Path>,
+    paths: &SitePaths,
+) -> Result<FontArc, Error> {
+    FontArc::try_from_vec(bytes).map_err(|e| {
+        let error = Error::new(ErrorKind::Image, format!("can't load card font: {}", e));
+        match configured {
+            Some(path) => error.with_path(&paths.content_root.join(path)),
+            None => error,
+        }
+    })
+}
+
+/// The largest title size whose wrapped lines fit in `height`, falling back
+/// to the smallest size with the last line cut short
+fn fit_title(font: &FontArc, title: &str, max_width: f32, height: f32) -> (f32, Vec<String>) {
+    let max_lines = |size: f32| ((height / (size * LINE_HEIGHT)) as usize).max(1);
+    for size in TITLE_SIZES {
+        let lines = wrap(font, size, title, max_width);
+        if lines.len() <= max_lines(size) {
+            return (size, lines);
+        }
+    }
+    let size = TITLE_SIZES[TITLE_SIZES.len() - 1];
+    let mut lines = wrap(font, size, title, max_width);
+    lines.truncate(max_lines(size));
+    if let Some(last) = lines.last_mut() {
+        *last = truncate(font, size, &format!("{}…", last), max_width);
+    }
+    (size, lines)
+}
+
+/// Breaks `text` into lines at spaces. A single word wider than a line gets
+/// a line of its own and is cut short.
+fn wrap(font: &FontArc, size: f32, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = match line.is_empty() {
+            true => word.to_string(),
+            false => format!("{} {}", line, word),
+        };
+        if line.is_empty() || text_width(font, size, &candidate) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+        .into_iter()
+        .map(|line| truncate(font, size, &line, max_width))
+        .collect()
+}
+
+/// `text`, cut short with an ellipsis if it's wider than `max_width`
+fn truncate(font: &FontArc, size: f32, text: &str, max_width: f32) -> String {
+    if text_width(font, size, text) <= max_width {
+        return text.to_string();
+    }
+    let mut chars: Vec<char> = text.trim_end_matches('…').chars().collect();
+    while !chars.is_empty() {
+        let candidate: String = chars.iter().collect::<String>().trim_end().to_string() + "…";
+        if text_width(font, size, &candidate) <= max_width {
+            return candidate;
+        }
+        chars.pop();
+    }
+    "…".to_string()
+}
+
+fn text_width(font: &FontArc, size: f32, text: &str) -> f32 {
+    layout(font, size, text)
+        .last()
+        .map_or(0.0, |&(_, x, advance)| x + advance)
+}
+
+/// Glyphs of `text` with their horizontal offsets and advances, kerned
+fn layout(font: &FontArc, size: f32, text: &str) -> Vec<(GlyphId, f32, f32)> {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous: Option<GlyphId> = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let advance = scaled.h_advance(id);
+        glyphs.push((id, caret, advance));
+        caret += advance;
+        previous = Some(id);
+    }
+    glyphs
+}
+
+struct Canvas<'a> {
+    image: &'a mut RgbImage,
+    align: CardAlign,
+}
+
+impl Canvas<'_> {
+    /// Draws one line of text with its baseline at `baseline`
+    fn text(&mut self, font: &FontArc, size: f32, color: Color, baseline: f32, text: &str) {
+        let x = match self.align {
+            CardAlign::Left => PADDING,
+            CardAlign::Center => (WIDTH as f32 - text_width(font, size, text)) / 2.0,
+        };
+        for (id, offset, _) in layout(font, size, text) {
+            let glyph = id.with_scale_and_position(size, point(x + offset, baseline));
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i64 + gx as i64;
+                let py = bounds.min.y as i64 + gy as i64;
+                if (0..WIDTH as i64).contains(&px) && (0..HEIGHT as i64).contains(&py) {
+                    let pixel = self.image.get_pixel_mut(px as u32, py as u32);
+                    blend(pixel, color, coverage);
+                }
+            });
+        }
+    }
+}
+
+fn blend(pixel: &mut Rgb<u8>, color: Color, coverage: f32) {
+    let coverage = coverage.clamp(0.0, 1.0);
+    for (channel, target) in pixel.0.iter_mut().zip(color.0) {
+        let mixed = *channel as f32 * (1.0 - coverage) + target as f32 * coverage;
+        *channel = mixed.round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_titles_wrap_and_shrink() {
+        let font = FontArc::try_from_slice(TITLE_FONT).unwrap();
+        let (size, lines) = fit_title(&font, "Short", 1040.0, 300.0);
+        assert_eq!((size, lines.len()), (72.0, 1));
+
+        let title = "A very long title about home networking ".repeat(6);
+        let (size, lines) = fit_title(&font, &title, 1040.0, 300.0);
+        assert_eq!(size, 44.0);
+        assert_eq!(lines.len(), 5);
+        assert!(lines[4].ends_with('…'), "{:?}", lines);
+        assert!(lines
+            .iter()
+            .all(|line| text_width(&font, size, line) <= 1040.0));
+    }
+}
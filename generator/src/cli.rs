@@ -0,0 +1,185 @@
+use crate::build::{BuildOptions, SitePaths};
+use crate::config::SiteConfig;
+use chrono::Local;
+use clap::{Args, Parser, Subcommand};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Exit status when content fails to parse or a check finds problems
+pub const EXIT_CONTENT_ERROR: i32 = 1;
+/// Exit status for bad configuration (clap also uses 2 for usage errors)
+pub const EXIT_CONFIG_ERROR: i32 = 2;
+
+#[derive(Parser)]
+#[command(
+    name = "nicolaschan-generator",
+    version,
+    about = "Static site generator for the blog",
+    after_help = "Exit status: 0 on success, 1 if content failed to build (without --keep-going) or check, 2 on usage or config errors."
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub site: SiteArgs,
+
+    /// Defaults to `build` when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Args)]
+pub struct SiteArgs {
+    /// Content root containing posts/, pages/ and resources/
+    /// [default: nearest `content` directory in or above the working directory]
+    #[arg(long, short = 'c', global = true, value_name = "DIR")]
+    pub content: Option<PathBuf>,
+
+    /// Output directory [default: `dist` next to the content root]
+    #[arg(long, short = 'o', global = true, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// Site config file [default: site.toml or site.yaml in the content root]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Override the configured base URL (e.g. for preview deployments)
+    #[arg(long, global = true, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// Incremental build cache [default: `.site-cache` next to the output directory]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Ignore the build cache and render everything from scratch
+    #[arg(long, global = true, conflicts_with = "cache_dir")]
+    pub no_cache: bool,
+
+    /// Include posts and pages marked `draft: true`
+    #[arg(long, global = true)]
+    pub drafts: bool,
+
+    /// Include posts scheduled for a future date
+    #[arg(long, global = true)]
+    pub future: bool,
+
+    /// Skip files with errors instead of failing the build
+    #[arg(long, global = true)]
+    pub keep_going: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render the site into the output directory
+    Build,
+    /// Build the site and serve the output directory over HTTP
+    Serve {
+        /// Address to bind to
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Port to listen on
+        #[arg(long, short = 'p', default_value_t = 8000)]
+        port: u16,
+    },
+    /// Create a new post (or page) from a template
+    New {
+        /// Title of the new post
+        title: String,
+        /// URL slug [default: slugified title]
+        #[arg(long)]
+        slug: Option<String>,
+        /// Create a page under pages/ instead of a post
+        #[arg(long)]
+        page: bool,
+    },
+    /// Build the site, then check every page for broken links and missing
+    /// resources. External URLs are only checked for syntax unless
+    /// --external is given.
+    Check {
+        /// Also request external URLs and report ones that fail
+        #[arg(long)]
+        external: bool,
+    },
+}
+
+impl SiteArgs {
+    /// Resolves content and output directories against the working directory
+    pub fn paths(&self) -> Result<SitePaths, String> {
+        let cwd = env::current_dir().map_err(|e| format!("IO error: {}", e))?;
+        let content_root = match &self.content {
+            Some(dir) => cwd.join(dir),
+            None => find_content_root(&cwd).ok_or_else(|| {
+                "Could not find a `content` directory; pass --content".to_string()
+            })?,
+        };
+        if !content_root.is_dir() {
+            return Err(format!(
+                "Content root {} is not a directory",
+                content_root.display()
+            ));
+        }
+
+        let output_dir = match &self.output {
+            Some(dir) => cwd.join(dir),
+            None => content_root
+                .parent()
+                .unwrap_or(&content_root)
+                .join("dist"),
+        };
+
+        let cache_dir = match &self.cache_dir {
+            _ if self.no_cache => None,
+            Some(dir) => Some(cwd.join(dir)),
+            None => Some(
+                output_dir
+                    .parent()
+                    .unwrap_or(&output_dir)
+                    .join(".site-cache"),
+            ),
+        };
+
+        Ok(SitePaths {
+            content_root,
+            output_dir,
+            cache_dir,
+        })
+    }
+
+    pub fn build_options(&self) -> BuildOptions {
+        BuildOptions {
+            drafts: self.drafts,
+            future: self.future,
+            today: Local::now().date_naive(),
+            keep_going: self.keep_going,
+        }
+    }
+
+    /// Loads the site config and applies command-line overrides
+    pub fn site_config(&self, paths: &SitePaths) -> Result<SiteConfig, String> {
+        let config_path = match &self.config {
+            Some(path) => Some(path.clone()),
+            None => SiteConfig::find(&paths.content_root),
+        };
+
+        let mut site = match config_path {
+            Some(path) => SiteConfig::load(&path)
+                .map_err(|e| format!("Error loading {}: {}", path.display(), e))?,
+            None => SiteConfig::default(),
+        };
+
+        if let Some(base_url) = &self.base_url {
+            site.base_url = base_url.clone();
+            site.validate()
+                .map_err(|e| format!("Invalid --base-url: {}", e))?;
+        }
+
+        Ok(site)
+    }
+}
+
+/// Walks up from `start` looking for a `content` directory, so the binary
+/// works from the repository root as well as from `generator/`
+fn find_content_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join("content"))
+        .find(|candidate| candidate.join("posts").is_dir())
+}
@@ -1,17 +1,493 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file names looked up at the content root, in order of preference
+pub const CONFIG_FILES: [&str; 3] = ["site.toml", "site.yaml", "site.yml"];
+
 /// Site-wide configuration and branding
-#[derive(Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SiteConfig {
-    pub name: &'static str,
-    pub tagline: &'static str,
-    pub description: &'static str,
+    pub name: String,
+    pub tagline: String,
+    pub description: String,
+    pub base_url: String,
+    /// Default author of every post
+    pub author: String,
+    /// Other authors, referenced by id from a post's `author`
+    #[serde(default)]
+    pub authors: Vec<Author>,
+    /// Reject frontmatter keys that no post or page field uses
+    #[serde(default)]
+    pub strict_frontmatter: bool,
+    #[serde(default = "default_language")]
+    pub language: String,
+    #[serde(default)]
+    pub nav: Vec<NavItem>,
+    #[serde(default)]
+    pub social: Vec<SocialLink>,
+    #[serde(default)]
+    pub robots: RobotsConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    #[serde(default)]
+    pub cards: CardConfig,
+    #[serde(default)]
+    pub related: RelatedConfig,
+    /// Set by `serve` to inject the live-reload client; never read from the file
+    #[serde(skip)]
+    pub live_reload: bool,
+}
+
+/// A link in the site navigation. Relative URLs are resolved against the
+/// site root; absolute URLs are used as-is.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NavItem {
+    pub label: String,
+    pub url: String,
+}
+
+/// A post author other than the site's own
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Author {
+    /// What posts write in `author:`
+    pub id: String,
+    pub name: String,
+    /// Homepage or profile, linked from the byline
+    pub url: Option<String>,
+}
+
+/// An external profile shown as an icon in the navigation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocialLink {
+    pub name: String,
+    pub url: String,
+    /// Name of a built-in icon (e.g. `github`); falls back to the link name
+    pub icon: Option<String>,
+}
+
+/// Crawler rules written to robots.txt, which always points at the sitemap
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RobotsConfig {
+    /// Site-relative paths (e.g. `/resources/`) crawlers should stay out of
+    #[serde(default)]
+    pub disallow: Vec<String>,
+    /// Paths to allow inside an otherwise disallowed prefix
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+/// Syntax highlighting themes for code blocks. Names are syntect's built-in
+/// themes or the file stem of a `.tmTheme` in the `highlighting` directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightConfig {
+    #[serde(default = "default_light_theme")]
+    pub light_theme: String,
+    #[serde(default = "default_dark_theme")]
+    pub dark_theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            light_theme: default_light_theme(),
+            dark_theme: default_dark_theme(),
+        }
+    }
+}
+
+/// Responsive variants generated for local JPEG, PNG and WebP images
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageConfig {
+    /// Widths in pixels to resize to; images are never scaled up
+    #[serde(default = "default_image_widths")]
+    pub widths: Vec<u32>,
+    /// Encoder quality from 1 to 100 for the lossy formats
+    #[serde(default = "default_image_quality")]
+    pub quality: u8,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            widths: default_image_widths(),
+            quality: default_image_quality(),
+        }
+    }
+}
+
+/// Link preview images drawn for posts without a cover image
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CardConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub align: CardAlign,
+    #[serde(default = "default_card_background")]
+    pub background: Color,
+    /// The title
+    #[serde(default = "default_card_text")]
+    pub text: Color,
+    /// The date, reading time and tags
+    #[serde(default = "default_card_muted")]
+    pub muted: Color,
+    /// The site name and the bar along the edge
+    #[serde(default = "default_card_accent")]
+    pub accent: Color,
+    /// TrueType or OpenType fonts relative to the content root, replacing
+    /// the bundled DejaVu Sans Bold and DejaVu Sans
+    pub title_font: Option<PathBuf>,
+    pub text_font: Option<PathBuf>,
+}
+
+impl Default for CardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            align: CardAlign::default(),
+            background: default_card_background(),
+            text: default_card_text(),
+            muted: default_card_muted(),
+            accent: default_card_accent(),
+            title_font: None,
+            text_font: None,
+        }
+    }
+}
+
+/// How text is lined up on a card
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CardAlign {
+    #[default]
+    Left,
+    Center,
+}
+
+/// An opaque color written as `#rrggbb`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii());
+        let channel = |i: usize| hex.and_then(|hex| u8::from_str_radix(&hex[i..i + 2], 16).ok());
+        match (channel(0), channel(2), channel(4)) {
+            (Some(r), Some(g), Some(b)) => Ok(Color([r, g, b])),
+            _ => Err(format!("expected a color like `#1a1a1a`, got {:?}", value)),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_card_background() -> Color {
+    Color([0x1a, 0x1a, 0x1a])
+}
+
+fn default_card_text() -> Color {
+    Color([0xe8, 0xe8, 0xe8])
+}
+
+fn default_card_muted() -> Color {
+    Color([0x99, 0x99, 0x99])
+}
+
+fn default_card_accent() -> Color {
+    Color([0x6c, 0xb6, 0xff])
+}
+
+/// How long post lists (the index and tag pages) are split into pages
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaginationConfig {
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            per_page: default_per_page(),
+        }
+    }
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+/// Suggestions shown below each post
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelatedConfig {
+    /// How many related posts to show; 0 turns them off
+    #[serde(default = "default_related_count")]
+    pub count: usize,
+}
+
+impl Default for RelatedConfig {
+    fn default() -> Self {
+        Self {
+            count: default_related_count(),
+        }
+    }
+}
+
+fn default_related_count() -> usize {
+    3
+}
+
+fn default_image_widths() -> Vec<u32> {
+    vec![480, 960, 1600]
+}
+
+fn default_image_quality() -> u8 {
+    75
+}
+
+fn default_light_theme() -> String {
+    "base16-ocean.light".to_string()
+}
+
+fn default_dark_theme() -> String {
+    "base16-ocean.dark".to_string()
+}
+
+fn default_language() -> String {
+    "en".to_string()
 }
 
 impl Default for SiteConfig {
     fn default() -> Self {
         Self {
-            name: "Nicolas Chan",
-            tagline: "Software engineering and anything else on my mind 🌁",
-            description: "Software engineering and anything else on my mind 🌁",
+            name: "Nicolas Chan".to_string(),
+            tagline: "Software engineering and anything else on my mind 🌁".to_string(),
+            description: "Software engineering and anything else on my mind 🌁".to_string(),
+            base_url: "https://nicolaschan.com/".to_string(),
+            author: "Nicolas Chan".to_string(),
+            authors: Vec::new(),
+            strict_frontmatter: false,
+            language: default_language(),
+            nav: vec![
+                NavItem { label: "Posts".to_string(), url: "".to_string() },
+                NavItem { label: "Tags".to_string(), url: "tags/".to_string() },
+                NavItem { label: "About".to_string(), url: "about/".to_string() },
+            ],
+            social: vec![SocialLink {
+                name: "GitHub".to_string(),
+                url: "https://github.com/nicolaschan".to_string(),
+                icon: Some("github".to_string()),
+            }],
+            robots: RobotsConfig::default(),
+            highlight: HighlightConfig::default(),
+            images: ImageConfig::default(),
+            pagination: PaginationConfig::default(),
+            cards: CardConfig::default(),
+            related: RelatedConfig::default(),
+            live_reload: false,
         }
     }
 }
+
+impl SiteConfig {
+    /// Finds the config file in a content directory, if there is one
+    pub fn find(content_root: &Path) -> Option<PathBuf> {
+        CONFIG_FILES
+            .iter()
+            .map(|name| content_root.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Loads and validates a TOML or YAML config file, chosen by extension
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("IO error: {}", e))?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&content)?,
+            Some("yaml") | Some("yml") => Self::from_yaml(&content)?,
+            _ => return Err("Config file must end in .toml, .yaml or .yml".to_string()),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| format!("TOML error: {}", e))
+    }
+
+    pub fn from_yaml(content: &str) -> Result<Self, String> {
+        serde_yaml::from_str(content).map_err(|e| format!("YAML error: {}", e))
+    }
+
+    /// Checks values that deserialize fine but would produce a broken site
+    pub fn validate(&self) -> Result<(), String> {
+        let required = [
+            ("name", &self.name),
+            ("author", &self.author),
+            ("language", &self.language),
+        ];
+        for (key, value) in required {
+            if value.trim().is_empty() {
+                return Err(format!("`{}` must not be empty", key));
+            }
+        }
+
+        if !is_absolute_url(&self.base_url) {
+            return Err(format!(
+                "`base_url` must be an absolute http(s) URL, got {:?}",
+                self.base_url
+            ));
+        }
+
+        for (i, author) in self.authors.iter().enumerate() {
+            if author.id.trim().is_empty() || author.name.trim().is_empty() {
+                return Err(format!("`authors[{}]` needs an `id` and a `name`", i));
+            }
+            if self.authors[..i].iter().any(|other| other.id == author.id) {
+                return Err(format!("`authors[{}].id` {:?} is used twice", i, author.id));
+            }
+            if author.url.as_deref().is_some_and(|url| !is_absolute_url(url)) {
+                return Err(format!(
+                    "`authors[{}].url` must be an absolute URL, got {:?}",
+                    i,
+                    author.url.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        for (i, item) in self.nav.iter().enumerate() {
+            if item.label.trim().is_empty() {
+                return Err(format!("`nav[{}].label` must not be empty", i));
+            }
+        }
+
+        for (i, link) in self.social.iter().enumerate() {
+            if link.name.trim().is_empty() {
+                return Err(format!("`social[{}].name` must not be empty", i));
+            }
+            if !is_absolute_url(&link.url) && !link.url.starts_with("mailto:") {
+                return Err(format!(
+                    "`social[{}].url` must be an absolute URL, got {:?}",
+                    i, link.url
+                ));
+            }
+        }
+
+        let rules = [("allow", &self.robots.allow), ("disallow", &self.robots.disallow)];
+        for (key, paths) in rules {
+            for (i, path) in paths.iter().enumerate() {
+                if !path.starts_with('/') {
+                    return Err(format!(
+                        "`robots.{}[{}]` must be a path starting with `/`, got {:?}",
+                        key, i, path
+                    ));
+                }
+            }
+        }
+
+        if self.images.widths.is_empty() || self.images.widths.contains(&0) {
+            return Err("`images.widths` must list at least one non-zero width".to_string());
+        }
+        if !(1..=100).contains(&self.images.quality) {
+            return Err(format!(
+                "`images.quality` must be between 1 and 100, got {}",
+                self.images.quality
+            ));
+        }
+
+        if self.pagination.per_page == 0 {
+            return Err("`pagination.per_page` must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// The configured author with the given id
+    pub fn find_author(&self, id: &str) -> Option<&Author> {
+        self.authors.iter().find(|author| author.id == id)
+    }
+
+    /// Display name of a post's author, defaulting to the site author
+    pub fn author_name(&self, id: Option<&str>) -> &str {
+        id.and_then(|id| self.find_author(id))
+            .map_or(&self.author, |author| &author.name)
+    }
+
+    /// Joins a site-relative path onto the base URL
+    pub fn absolute_url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+}
+
+impl NavItem {
+    /// The link target as seen from a page at the given depth
+    pub fn href(&self, path_prefix: &str) -> String {
+        if is_absolute_url(&self.url) {
+            self.url.clone()
+        } else {
+            format!("{}{}", path_prefix, self.url.trim_start_matches('/'))
+        }
+    }
+}
+
+impl SocialLink {
+    pub fn has_icon(&self, name: &str) -> bool {
+        self.icon.as_deref() == Some(name)
+    }
+}
+
+fn is_absolute_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_key_is_named() {
+        let err = SiteConfig::from_toml(
+            r#"
+name = "Blog"
+tagline = "t"
+description = "d"
+base_url = "https://example.com/"
+author = "Me"
+tagine = "typo"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.contains("tagine"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_names_bad_key() {
+        let mut config = SiteConfig::default();
+        config.social[0].url = "github.com/me".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("social[0].url"), "{}", err);
+    }
+}
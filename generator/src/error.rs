@@ -0,0 +1,201 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What stage of the build an error came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The `---` delimited frontmatter block is missing or unterminated
+    Frontmatter,
+    /// The frontmatter isn't valid YAML or doesn't match the expected fields
+    Yaml,
+    /// A frontmatter date couldn't be parsed
+    Date,
+    /// KaTeX rejected a math expression
+    Math,
+    /// A highlighting theme or syntax couldn't be found or loaded
+    Highlight,
+    /// An image couldn't be decoded or its variants encoded
+    Image,
+    /// An askama template failed to render
+    Template,
+    /// A generated page links to a file, anchor or URL that doesn't exist
+    Link,
+    /// A series has missing or duplicate parts
+    Series,
+    /// A post names an author that isn't in the site config
+    Author,
+    /// A page would be written over a page the site generates
+    Conflict,
+    /// Reading content or writing output failed
+    Io,
+}
+
+impl ErrorKind {
+    fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Frontmatter => "frontmatter",
+            ErrorKind::Yaml => "yaml",
+            ErrorKind::Date => "date",
+            ErrorKind::Math => "math",
+            ErrorKind::Highlight => "highlight",
+            ErrorKind::Image => "image",
+            ErrorKind::Template => "template",
+            ErrorKind::Link => "link",
+            ErrorKind::Series => "series",
+            ErrorKind::Author => "author",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Io => "io",
+        }
+    }
+}
+
+/// Whether a problem fails the build or is only reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One-based line and column in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Converts a byte offset into a line and (character) column
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source[..floor_char_boundary(source, offset)];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Location {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+fn floor_char_boundary(s: &str, mut offset: usize) -> usize {
+    while !s.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// A build error, optionally pinned to a file and a position in it
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub severity: Severity,
+    pub message: String,
+    pub path: Option<PathBuf>,
+    pub location: Option<Location>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            severity: Severity::Error,
+            message: message.into(),
+            path: None,
+            location: None,
+        }
+    }
+
+    pub fn io(path: &Path, error: std::io::Error) -> Self {
+        Error::new(ErrorKind::Io, error.to_string()).with_path(path)
+    }
+
+    pub fn template(path: &Path, error: askama::Error) -> Self {
+        Error::new(ErrorKind::Template, error.to_string()).with_path(path)
+    }
+
+    /// Downgrades the error to a warning, reported without failing the build
+    pub fn warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    /// Sets the location from a byte offset into `source`
+    pub fn at_offset(mut self, source: &str, offset: usize) -> Self {
+        self.location = Some(Location::from_offset(source, offset));
+        self
+    }
+
+    /// Attaches the file the error came from, unless one is already set
+    pub fn with_path(mut self, path: &Path) -> Self {
+        if self.path.is_none() {
+            self.path = Some(path.to_path_buf());
+        }
+        self
+    }
+
+    /// Formats the error like rustc: a header, the file position and, when
+    /// the file can be read, the offending line with a caret under the column
+    pub fn render(&self) -> String {
+        let mut out = self.to_string();
+        let (Some(path), Some(location)) = (&self.path, self.location) else {
+            return out;
+        };
+        let Some(line) = fs::read_to_string(path)
+            .ok()
+            .and_then(|source| source.lines().nth(location.line - 1).map(str::to_string))
+        else {
+            return out;
+        };
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        let caret_pad: String = line
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!(
+            "\n{gutter} |\n{line_no} | {line}\n{gutter} | {caret_pad}^",
+            line_no = location.line,
+        ));
+        out
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}[{}]: {}", severity, self.kind.code(), self.message)?;
+        match (&self.path, self.location) {
+            (Some(path), Some(loc)) => {
+                write!(f, "\n  --> {}:{}:{}", path.display(), loc.line, loc.column)
+            }
+            (Some(path), None) => write!(f, "\n  --> {}", path.display()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_from_offset() {
+        let source = "---\ntitle: é x\ndate: nope\n";
+        let offset = source.find("nope").unwrap();
+        assert_eq!(
+            Location::from_offset(source, offset),
+            Location { line: 3, column: 7 }
+        );
+        let offset = source.find('x').unwrap();
+        assert_eq!(
+            Location::from_offset(source, offset),
+            Location { line: 2, column: 10 }
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! Syntax highlighting for fenced code blocks. Code is marked up with
+//! CSS classes rather than inline colors, and a stylesheet is generated for
+//! a light and a dark theme so code follows the site's theme toggle.
+
+use crate::config::HighlightConfig;
+use crate::error::{Error, ErrorKind};
+use crate::markdown::html_escape;
+use once_cell::sync::Lazy;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use syntect::highlighting::{Color, FontStyle, Theme, ThemeSet};
+use syntect::html::{line_tokens_to_classed_spans, ClassStyle};
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Prefix for highlighting classes, so scopes like `comment` or `tag`
+/// can't collide with the site's own classes
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+static DEFAULT_SYNTAXES: Lazy<Arc<SyntaxSet>> =
+    Lazy::new(|| Arc::new(SyntaxSet::load_defaults_newlines()));
+
+/// Syntaxes used by [`code_block`]: the defaults plus any loaded from the
+/// project's highlighting directory
+static SYNTAX_SET: Lazy<RwLock<Arc<SyntaxSet>>> =
+    Lazy::new(|| RwLock::new(DEFAULT_SYNTAXES.clone()));
+
+/// Loads `.sublime-syntax` files from `dir` on top of the default syntaxes.
+/// Called at the start of every build so edits show up when serving.
+pub fn load_syntaxes(dir: &Path) -> Result<(), Error> {
+    let syntaxes = if has_files(dir, "sublime-syntax") {
+        let mut builder = (**DEFAULT_SYNTAXES).clone().into_builder();
+        builder
+            .add_from_folder(dir, true)
+            .map_err(|e| Error::new(ErrorKind::Highlight, e.to_string()).with_path(dir))?;
+        Arc::new(builder.build())
+    } else {
+        DEFAULT_SYNTAXES.clone()
+    };
+    *SYNTAX_SET.write().unwrap() = syntaxes;
+    Ok(())
+}
+
+/// The built-in themes plus any `.tmTheme` files in `dir`, named after
+/// their file stem
+fn load_themes(dir: &Path) -> Result<ThemeSet, Error> {
+    let mut themes = ThemeSet::load_defaults();
+    if has_files(dir, "tmTheme") {
+        themes
+            .add_from_folder(dir)
+            .map_err(|e| Error::new(ErrorKind::Highlight, e.to_string()).with_path(dir))?;
+    }
+    Ok(themes)
+}
+
+fn has_files(dir: &Path, extension: &str) -> bool {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .any(|e| e.path().extension().is_some_and(|ext| ext == extension))
+}
+
+/// Options parsed from a fenced code block's info string
+#[derive(Debug, Default, PartialEq)]
+struct CodeInfo {
+    lang: String,
+    filename: Option<String>,
+    /// Lines to highlight, numbered from 1 within the block regardless of
+    /// where line numbering starts
+    highlighted: Vec<RangeInclusive<usize>>,
+    /// First line number, when lines are numbered
+    line_numbers: Option<usize>,
+    /// Lines starting with `+` or `-` are additions or removals
+    diff: bool,
+}
+
+impl CodeInfo {
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted.iter().any(|range| range.contains(&line))
+    }
+}
+
+/// Renders a fenced code block with a header showing the language and
+/// filename from the info string. Each line is wrapped in a `.line` span
+/// carrying its number, highlight and diff state.
+pub fn code_block(code: &str, lang_info: &str) -> String {
+    let info = parse_code_info(lang_info);
+
+    let syntax_set = SYNTAX_SET.read().unwrap().clone();
+    let syntax = syntax_set
+        .find_syntax_by_token(&info.lang)
+        .or_else(|| syntax_set.find_syntax_by_extension(&info.lang))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+
+    let mut parse_state = ParseState::new(syntax);
+    let mut scopes = ScopeStack::new();
+    let mut html_output = String::new();
+
+    for (i, line) in LinesWithEndings::from(code).enumerate() {
+        let number = i + 1;
+        let (marker, line) = match line.strip_prefix(['+', '-']) {
+            Some(rest) if info.diff => (&line[..1], rest),
+            _ => ("", line),
+        };
+
+        let mut classes = String::from("line");
+        if info.is_highlighted(number) {
+            classes.push_str(" highlighted");
+        }
+        match marker {
+            "+" => classes.push_str(" diff-add"),
+            "-" => classes.push_str(" diff-remove"),
+            _ => {}
+        }
+        let _ = write!(html_output, "<span class=\"{}\"", classes);
+        if let Some(start) = info.line_numbers {
+            let _ = write!(html_output, " data-line=\"{}\"", start + i);
+        }
+        html_output.push('>');
+        if info.diff {
+            let _ = write!(
+                html_output,
+                "<span class=\"diff-marker\" data-marker=\"{}\"></span>",
+                marker
+            );
+        }
+
+        // Scopes can span lines, so reopen the ones still open from the
+        // previous line and close them again at the end of this one
+        let reopened = scopes.len();
+        for scope in scopes.as_slice() {
+            let _ = write!(html_output, "<span class=\"{}\">", scope_classes(*scope));
+        }
+        let spans = parse_state
+            .parse_line(line, &syntax_set)
+            .map_err(|e| e.to_string())
+            .and_then(|ops| {
+                line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut scopes)
+                    .map_err(|e| e.to_string())
+            });
+        match spans {
+            Ok((spans, _)) => html_output.push_str(&spans),
+            Err(_) => {
+                // Fall back to plain text for the rest of the block
+                html_output.push_str(&"</span>".repeat(reopened));
+                scopes = ScopeStack::new();
+                parse_state = ParseState::new(syntax_set.find_syntax_plain_text());
+                html_output.push_str(&html_escape(line));
+            }
+        }
+        html_output.push_str(&"</span>".repeat(scopes.len()));
+        html_output.push_str("</span>");
+    }
+
+    let mut pre_classes = Vec::new();
+    if !info.lang.is_empty() {
+        pre_classes.push(format!("language-{}", html_escape(&info.lang)));
+    }
+    if info.line_numbers.is_some() {
+        pre_classes.push("line-numbers".to_string());
+    }
+    if info.diff {
+        pre_classes.push("diff".to_string());
+    }
+    let pre_class = if pre_classes.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"{}\"", pre_classes.join(" "))
+    };
+
+    let header = build_code_header(&info.lang, info.filename.as_deref());
+    format!(
+        "<div class=\"code-block\">{}<pre{}><code>{}</code></pre></div>",
+        header, pre_class, html_output
+    )
+}
+
+/// Class attribute `line_tokens_to_classed_spans` uses for a scope, e.g.
+/// `hl-string hl-quoted` for `string.quoted`
+fn scope_classes(scope: Scope) -> String {
+    scope
+        .build_string()
+        .split('.')
+        .map(|atom| format!("hl-{}", atom))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// CSS for the configured light and dark themes. The light theme is the
+/// default; the dark one applies when the toggle picks dark, or when the
+/// system prefers dark and the toggle hasn't picked light. Each theme also
+/// sets the code block background and text colors used by the site styles.
+pub fn stylesheet(config: &HighlightConfig, dir: &Path) -> Result<String, Error> {
+    let themes = load_themes(dir)?;
+    let theme = |name: &str, key: &str| {
+        themes.themes.get(name).ok_or_else(|| {
+            let mut available: Vec<&str> = themes.themes.keys().map(String::as_str).collect();
+            available.sort_unstable();
+            Error::new(
+                ErrorKind::Highlight,
+                format!(
+                    "unknown theme {:?} for `highlight.{}`; available themes: {}",
+                    name,
+                    key,
+                    available.join(", ")
+                ),
+            )
+        })
+    };
+    let light = theme(&config.light_theme, "light_theme")?;
+    let dark = theme(&config.dark_theme, "dark_theme")?;
+
+    let mut css = String::from("/* Generated from the configured highlighting themes */\n");
+    theme_css(&mut css, light, ":root");
+    theme_css(&mut css, dark, "[data-theme=\"dark\"]");
+    css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+    theme_css(&mut css, dark, ":root:not([data-theme=\"light\"])");
+    css.push_str("}\n");
+    Ok(css)
+}
+
+/// Writes a theme's rules with every selector scoped under `scope`
+fn theme_css(css: &mut String, theme: &Theme, scope: &str) {
+    let _ = writeln!(css, "\n{} {{", scope);
+    if let Some(bg) = theme.settings.background {
+        let _ = writeln!(css, "    --color-pre-bg: {};", hex(bg));
+    }
+    if let Some(fg) = theme.settings.foreground {
+        let _ = writeln!(css, "    --color-pre-text: {};", hex(fg));
+    }
+    css.push_str("}\n");
+
+    for item in &theme.scopes {
+        let selectors: Vec<String> = item
+            .scope
+            .selectors
+            .iter()
+            .map(|selector| {
+                let path: Vec<String> = selector
+                    .extract_scopes()
+                    .into_iter()
+                    .map(scope_selector)
+                    .collect();
+                format!("{} {}", scope, path.join(" "))
+            })
+            .collect();
+        if selectors.is_empty() {
+            continue;
+        }
+
+        let mut declarations = String::new();
+        if let Some(fg) = item.style.foreground {
+            let _ = writeln!(declarations, "    color: {};", hex(fg));
+        }
+        if let Some(bg) = item.style.background {
+            let _ = writeln!(declarations, "    background-color: {};", hex(bg));
+        }
+        if let Some(font_style) = item.style.font_style {
+            if font_style.contains(FontStyle::BOLD) {
+                declarations.push_str("    font-weight: bold;\n");
+            }
+            if font_style.contains(FontStyle::ITALIC) {
+                declarations.push_str("    font-style: italic;\n");
+            }
+            if font_style.contains(FontStyle::UNDERLINE) {
+                declarations.push_str("    text-decoration: underline;\n");
+            }
+        }
+        if !declarations.is_empty() {
+            let _ = write!(css, "{} {{\n{}}}\n", selectors.join(",\n"), declarations);
+        }
+    }
+}
+
+/// Selector matching the classes `ClassedHTMLGenerator` puts on a scope,
+/// e.g. `.hl-string.hl-quoted` for `string.quoted`
+fn scope_selector(scope: Scope) -> String {
+    scope
+        .build_string()
+        .split('.')
+        .map(|atom| format!(".hl-{}", css_escape(atom)))
+        .collect()
+}
+
+/// Escapes characters that aren't valid in a CSS class name, e.g. `c++`
+fn css_escape(atom: &str) -> String {
+    atom.chars().fold(String::new(), |mut escaped, c| {
+        if !(c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+fn hex(color: Color) -> String {
+    if color.a == 0xFF {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    } else {
+        format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            color.r, color.g, color.b, color.a
+        )
+    }
+}
+
+/// Parses an info string such as `rust:src/main.rs {3,5-7} linenos=10`.
+/// The first word is the language, optionally followed by `:filename`;
+/// a `diff-` prefix on the language marks a diff of that language. The
+/// remaining words may be `filename=path`, `linenos`, `linenos=N` and a
+/// `{...}` list of lines or ranges to highlight.
+fn parse_code_info(info: &str) -> CodeInfo {
+    let mut info = info.trim().to_string();
+    let mut code_info = CodeInfo::default();
+
+    // Pull out the highlighted lines first; they may follow the language
+    // without a space, as in `rust{3}`
+    if let (Some(start), Some(end)) = (info.find('{'), info.rfind('}')) {
+        if start < end {
+            code_info.highlighted = parse_line_ranges(&info[start + 1..end]);
+            info.replace_range(start..=end, " ");
+        }
+    }
+
+    let mut words = info.split_whitespace();
+    let first = words.next().unwrap_or("");
+
+    // Check for "lang:filename" format
+    let lang = match first.split_once(':') {
+        Some((lang, filename)) => {
+            let filename: Vec<&str> = std::iter::once(filename)
+                .chain(words.by_ref().take_while(|w| !is_option(w)))
+                .filter(|w| !w.is_empty())
+                .collect();
+            if !filename.is_empty() {
+                code_info.filename = Some(filename.join(" "));
+            }
+            lang
+        }
+        None => first,
+    };
+    match lang.strip_prefix("diff-") {
+        Some(lang) => {
+            code_info.diff = true;
+            code_info.lang = lang.to_string();
+        }
+        None => code_info.lang = lang.to_string(),
+    }
+
+    for word in info.split_whitespace().skip(1) {
+        if let Some(filename) = word.strip_prefix("filename=") {
+            code_info.filename = Some(filename.to_string());
+        } else if word == "linenos" {
+            code_info.line_numbers = Some(1);
+        } else if let Some(start) = word.strip_prefix("linenos=") {
+            code_info.line_numbers = start.parse().ok().or(Some(1));
+        }
+    }
+
+    code_info
+}
+
+fn is_option(word: &str) -> bool {
+    word.starts_with("filename=") || word == "linenos" || word.starts_with("linenos=")
+}
+
+/// Parses `3,5-7` into line ranges, ignoring anything malformed
+fn parse_line_ranges(list: &str) -> Vec<RangeInclusive<usize>> {
+    list.split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            match part.split_once('-') {
+                Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+                None => part.parse().ok().map(|line| line..=line),
+            }
+        })
+        .collect()
+}
+
+fn build_code_header(lang: &str, filename: Option<&str>) -> String {
+    let copy_button = r#"<button class="code-block-copy" title="Copy code"><svg class="copy-icon" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"><rect x="9" y="9" width="13" height="13" rx="2" ry="2"></rect><path d="M5 15H4a2 2 0 0 1-2-2V4a2 2 0 0 1 2-2h9a2 2 0 0 1 2 2v1"></path></svg><svg class="check-icon" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"><polyline points="20 6 9 17 4 12"></polyline></svg></button>"#;
+
+    match (lang.is_empty(), filename) {
+        (true, None) => {
+            format!(
+                "<div class=\"code-block-header\"><div class=\"code-block-header-content\"></div>{}</div>",
+                copy_button
+            )
+        }
+        (false, None) => {
+            format!(
+                "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-lang\">{}</span></div>{}</div>",
+                html_escape(lang),
+                copy_button
+            )
+        }
+        (true, Some(f)) => {
+            format!(
+                "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-filename\">{}</span></div>{}</div>",
+                html_escape(f),
+                copy_button
+            )
+        }
+        (false, Some(f)) => {
+            format!(
+                "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-lang\">{}</span><span class=\"separator\">·</span><span class=\"code-block-filename\">{}</span></div>{}</div>",
+                html_escape(lang),
+                html_escape(f),
+                copy_button
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_code_info() {
+        let info = parse_code_info("diff-rust:src/main.rs {3,5-7} linenos=10");
+        assert_eq!(
+            info,
+            CodeInfo {
+                lang: "rust".to_string(),
+                filename: Some("src/main.rs".to_string()),
+                highlighted: vec![3..=3, 5..=7],
+                line_numbers: Some(10),
+                diff: true,
+            }
+        );
+
+        let info = parse_code_info("python{2} filename=app.py linenos");
+        assert_eq!(info.lang, "python");
+        assert_eq!(info.filename.as_deref(), Some("app.py"));
+        assert_eq!(info.highlighted, vec![2..=2]);
+        assert_eq!(info.line_numbers, Some(1));
+        assert!(!info.diff);
+    }
+
+    #[test]
+    fn test_code_block_wraps_lines() {
+        let html = code_block(
+            "fn a() {}\n-let x = 1;\n+let x = 2;\n",
+            "diff-rust {1} linenos=5",
+        );
+        assert!(
+            html.contains(r#"<pre class="language-rust line-numbers diff">"#),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains(r#"<span class="line highlighted" data-line="5">"#),
+            "{}",
+            html
+        );
+        assert!(html.contains(r#"<span class="line diff-remove" data-line="6"><span class="diff-marker" data-marker="-"></span>"#), "{}", html);
+        assert!(html.contains(r#"<span class="line diff-add" data-line="7"><span class="diff-marker" data-marker="+"></span>"#), "{}", html);
+        // Changed lines are still highlighted as Rust, without their marker
+        assert!(!html.contains("+let"), "{}", html);
+    }
+
+    #[test]
+    fn test_language_class_is_escaped() {
+        let html = code_block("x\n", r#"a"onclick="b"#);
+        assert!(
+            html.contains(r#"<pre class="language-a&quot;onclick=&quot;b">"#),
+            "{}",
+            html
+        );
+    }
+
+    #[test]
+    fn test_custom_syntax_and_unknown_theme() {
+        let dir = std::env::temp_dir().join(format!("generator-highlight-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("nix.sublime-syntax"),
+            "%YAML 1.2\n---\nname: Nix\nfile_extensions: [nix]\nscope: source.nix\n\
+             contexts:\n  main:\n    - match: '\\b(let|in)\\b'\n      scope: keyword.other.nix\n",
+        )
+        .unwrap();
+
+        load_syntaxes(&dir).unwrap();
+        let html = code_block("let x = 1; in x\n", "nix");
+        load_syntaxes(&dir.join("missing")).unwrap();
+        assert!(
+            html.contains(r#"<span class="hl-keyword hl-other hl-nix">let</span>"#),
+            "{}",
+            html
+        );
+
+        let config = HighlightConfig {
+            light_theme: "nope".to_string(),
+            ..HighlightConfig::default()
+        };
+        let err = stylesheet(&config, &dir).unwrap_err();
+        assert!(err.message.contains("highlight.light_theme"), "{}", err);
+
+        let css = stylesheet(&HighlightConfig::default(), &dir).unwrap();
+        assert!(css.contains("[data-theme=\"dark\"] .hl-comment"), "{}", css);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,631 @@
+//! Responsive images. Local JPEG, PNG and WebP images referenced from
+//! markdown or `cover_image` are resized to the configured widths and
+//! re-encoded as AVIF and WebP, then served through `<picture>` with a
+//! `srcset` per format. Every local image gets its dimensions so the page
+//! doesn't shift as it loads, and images in the body load lazily.
+
+use crate::build::{write_file, SitePaths};
+use crate::cache::{hash_bytes, BuildCache};
+use crate::config::ImageConfig;
+use crate::error::{Error, ErrorKind};
+use crate::links::resolve;
+use crate::markdown::{html_escape, slugify};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader, ImageResult};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Where generated variants go, relative to the output directory
+const OUTPUT_DIR: &str = "images";
+
+/// Images in the body are at most as wide as the content column
+const CONTENT_SIZES: &str = "(max-width: 720px) 100vw, 720px";
+
+/// Covers also span the column's padding
+const COVER_SIZES: &str = "(max-width: 800px) 100vw, 800px";
+
+/// rav1e speed from 1 (slowest) to 10; 6 is a good size/time trade-off
+const AVIF_SPEED: u8 = 6;
+
+static IMG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<img\s[^>]*>").unwrap());
+/// One attribute in any of HTML's forms: `name="value"`, `name='value'`,
+/// `name=value` or a bare `name`
+static ATTR_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^\s+([^\s"'>/=]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#).unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Avif,
+    Webp,
+    Jpeg,
+    Png,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Avif => "avif",
+            Format::Webp => "webp",
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Format::Avif => "image/avif",
+            Format::Webp => "image/webp",
+            Format::Jpeg => "image/jpeg",
+            Format::Png => "image/png",
+        }
+    }
+
+    /// Format for browsers without AVIF or WebP support, based on the
+    /// source extension. `None` for images that shouldn't be re-encoded,
+    /// like animated GIFs.
+    fn fallback_for(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "png" => Some(Format::Png),
+            "webp" => Some(Format::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// One resized, re-encoded copy of an image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    /// Site-relative URL
+    pub path: String,
+    pub width: u32,
+}
+
+/// Variants of one format, smallest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PictureSource {
+    pub mime: String,
+    pub variants: Vec<Variant>,
+}
+
+/// An image with generated variants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Picture {
+    /// Dimensions of the largest variant, after applying EXIF orientation
+    pub width: u32,
+    pub height: u32,
+    /// Most preferred format first; the last one is the `<img>` fallback
+    pub sources: Vec<PictureSource>,
+}
+
+impl Picture {
+    /// The largest variant in the most widely supported format, for the
+    /// `<img>` fallback and link previews
+    pub fn largest_fallback(&self) -> &Variant {
+        let fallback = self.sources.last().expect("a picture has a source");
+        fallback.variants.last().expect("a source has a variant")
+    }
+
+    /// `<picture>` markup for a page `prefix` below the site root (e.g.
+    /// `../../`). `attrs` are extra `<img>` attributes, already escaped.
+    pub fn html(&self, prefix: &str, sizes: &str, attrs: &[(String, String)]) -> String {
+        let srcset = |source: &PictureSource| {
+            source
+                .variants
+                .iter()
+                .map(|v| format!("{}{} {}w", prefix, v.path, v.width))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let (fallback, preferred) = self.sources.split_last().expect("a picture has a source");
+        let mut html = String::from("<picture>");
+        for source in preferred {
+            html.push_str(&format!(
+                "<source type=\"{}\" srcset=\"{}\" sizes=\"{}\">",
+                source.mime,
+                srcset(source),
+                sizes
+            ));
+        }
+        let largest = self.largest_fallback();
+        html.push_str(&format!(
+            "<img src=\"{}{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\"",
+            prefix,
+            largest.path,
+            srcset(fallback),
+            sizes,
+            self.width,
+            self.height
+        ));
+        for (name, value) in attrs {
+            if !matches!(
+                name.as_str(),
+                "src" | "srcset" | "sizes" | "width" | "height"
+            ) {
+                html.push_str(&format!(" {}=\"{}\"", name, value));
+            }
+        }
+        html.push_str(" /></picture>");
+        html
+    }
+}
+
+/// A local image: its dimensions, plus variants if it can be re-encoded
+#[derive(Clone)]
+struct LocalImage {
+    width: u32,
+    height: u32,
+    picture: Option<Picture>,
+}
+
+/// Generates image variants into the output directory, skipping ones the
+/// build cache says are already there
+pub struct Images<'a> {
+    config: &'a ImageConfig,
+    resources_dir: PathBuf,
+    output_dir: PathBuf,
+    cache: &'a mut BuildCache,
+    /// Images seen so far by site path; `None` if not a readable local image
+    processed: HashMap<String, Option<LocalImage>>,
+    /// Variants encoded by this build
+    pub generated: usize,
+    /// Variants left as they were because their source didn't change
+    pub reused: usize,
+}
+
+impl<'a> Images<'a> {
+    pub fn new(config: &'a ImageConfig, paths: &SitePaths, cache: &'a mut BuildCache) -> Self {
+        Images {
+            config,
+            resources_dir: paths.resources_dir(),
+            output_dir: paths.output_dir.clone(),
+            cache,
+            processed: HashMap::new(),
+            generated: 0,
+            reused: 0,
+        }
+    }
+
+    /// Responsive versions of an image as referenced from the page at
+    /// `page_path`, if it's a local image that can be re-encoded
+    pub fn picture(&mut self, src: &str, page_path: &str) -> Result<Option<Picture>, Error> {
+        let Some(path) = resolve(src, page_path) else {
+            return Ok(None);
+        };
+        Ok(self.local(&path)?.and_then(|image| image.picture))
+    }
+
+    /// Rewrites the `<img>` tags in rendered HTML for a page at `page_path`:
+    /// local images become `<picture>`s with their dimensions, and every
+    /// image is lazy-loaded
+    pub fn rewrite_html(&mut self, html: &str, page_path: &str) -> Result<String, Error> {
+        let mut output = String::with_capacity(html.len());
+        let mut last = 0;
+        for tag in IMG_RE.find_iter(html) {
+            output.push_str(&html[last..tag.start()]);
+            output.push_str(&self.rewrite_img(tag.as_str(), page_path)?);
+            last = tag.end();
+        }
+        output.push_str(&html[last..]);
+        Ok(output)
+    }
+
+    fn rewrite_img(&mut self, tag: &str, page_path: &str) -> Result<String, Error> {
+        // Rebuilding a tag that doesn't fully parse would drop attributes
+        let Some(mut attrs) = parse_attrs(tag) else {
+            return Ok(tag.to_string());
+        };
+        let has = |attrs: &[(String, String)], name: &str| attrs.iter().any(|(n, _)| n == name);
+        // Leave hand-written responsive images alone
+        if has(&attrs, "srcset") {
+            return Ok(tag.to_string());
+        }
+
+        let src = attrs
+            .iter()
+            .find(|(name, _)| name == "src")
+            .map(|(_, src)| src.clone());
+        let local = match src.and_then(|src| resolve(&src, page_path)) {
+            Some(path) => self.local(&path)?,
+            None => None,
+        };
+
+        for (name, value) in [("loading", "lazy"), ("decoding", "async")] {
+            if !has(&attrs, name) {
+                attrs.push((name.to_string(), value.to_string()));
+            }
+        }
+        if let Some(image) = local {
+            if let Some(picture) = image.picture {
+                return Ok(picture.html(&page_prefix(page_path), CONTENT_SIZES, &attrs));
+            }
+            if !has(&attrs, "width") && !has(&attrs, "height") {
+                attrs.push(("width".to_string(), image.width.to_string()));
+                attrs.push(("height".to_string(), image.height.to_string()));
+            }
+        }
+
+        let attrs: String = attrs
+            .iter()
+            .map(|(name, value)| format!(" {}=\"{}\"", name, value))
+            .collect();
+        Ok(format!("<img{} />", attrs))
+    }
+
+    /// Reads a local image by site path (e.g. `resources/img/a.jpg`),
+    /// generating its variants the first time it's seen
+    fn local(&mut self, site_path: &str) -> Result<Option<LocalImage>, Error> {
+        if let Some(image) = self.processed.get(site_path) {
+            return Ok(image.clone());
+        }
+        let image = match site_path.strip_prefix("resources/") {
+            Some(relative) => self.process(&self.resources_dir.join(relative))?,
+            None => None,
+        };
+        self.processed.insert(site_path.to_string(), image.clone());
+        Ok(image)
+    }
+
+    fn process(&mut self, path: &Path) -> Result<Option<LocalImage>, Error> {
+        let Ok(bytes) = fs::read(path) else {
+            return Ok(None);
+        };
+        let image_error = |e: image::ImageError| {
+            Error::new(ErrorKind::Image, format!("can't decode image: {}", e)).with_path(path)
+        };
+        // Only the header is read here; unchanged variants don't need decoding
+        let (width, height) = match dimensions(&bytes) {
+            Ok(dimensions) => dimensions,
+            Err(image::ImageError::Unsupported(_)) => return Ok(None),
+            Err(e) => return Err(image_error(e)),
+        };
+        let Some(fallback) = Format::fallback_for(path) else {
+            return Ok(Some(LocalImage {
+                width,
+                height,
+                picture: None,
+            }));
+        };
+
+        let hash = hash_bytes(&bytes);
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(slugify)
+            .unwrap_or_default();
+        let mut formats = vec![Format::Avif, Format::Webp, fallback];
+        formats.dedup();
+
+        let mut sources = Vec::new();
+        let mut stale = Vec::new();
+        for format in formats {
+            let mut variants = Vec::new();
+            for variant_width in self.variant_widths(width) {
+                let name = format!(
+                    "{}-{}-{}.{}",
+                    stem,
+                    &hash[..8],
+                    variant_width,
+                    format.extension()
+                );
+                variants.push(Variant {
+                    path: format!("{}/{}", OUTPUT_DIR, name),
+                    width: variant_width,
+                });
+
+                // Variants only depend on the source and the encoder settings,
+                // so a new generator binary doesn't re-encode every image
+                let output_path = self.output_dir.join(OUTPUT_DIR).join(&name);
+                let fingerprint = hash_bytes(
+                    format!(
+                        "{}:{}:{:?}:{}",
+                        hash, variant_width, format, self.config.quality
+                    )
+                    .as_bytes(),
+                );
+                if self.cache.is_fresh(&output_path, &fingerprint) {
+                    self.reused += 1;
+                } else {
+                    stale.push((output_path, fingerprint, variant_width, format));
+                }
+            }
+            sources.push(PictureSource {
+                mime: format.mime().to_string(),
+                variants,
+            });
+        }
+
+        if !stale.is_empty() {
+            let source = decode(&bytes).map_err(image_error)?;
+            let quality = self.config.quality;
+            // Encoders are single-threaded, so variants are encoded in parallel
+            let encoded: Vec<Result<Vec<u8>, String>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = stale
+                    .iter()
+                    .map(|&(_, _, variant_width, format)| {
+                        let source = &source;
+                        scope.spawn(move || {
+                            let resized = if variant_width == width {
+                                source.clone()
+                            } else {
+                                source.resize_exact(
+                                    variant_width,
+                                    scaled_height(width, height, variant_width),
+                                    FilterType::Lanczos3,
+                                )
+                            };
+                            encode(&resized, format, quality)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("image encoder panicked"))
+                    .collect()
+            });
+
+            for ((output_path, fingerprint, _, format), encoded) in stale.into_iter().zip(encoded) {
+                let encoded = encoded.map_err(|e| {
+                    Error::new(
+                        ErrorKind::Image,
+                        format!("can't encode {} variant: {}", format.extension(), e),
+                    )
+                    .with_path(path)
+                })?;
+                write_file(&output_path, &encoded)?;
+                println!("Generated: {}", output_path.display());
+                self.cache.record_output(&output_path, &fingerprint);
+                self.generated += 1;
+            }
+        }
+
+        let largest = self.variant_widths(width).last().copied().unwrap_or(width);
+        Ok(Some(LocalImage {
+            width,
+            height,
+            picture: Some(Picture {
+                width: largest,
+                height: scaled_height(width, height, largest),
+                sources,
+            }),
+        }))
+    }
+
+    /// Configured widths, capped at the image's own width so nothing is
+    /// scaled up
+    fn variant_widths(&self, width: u32) -> Vec<u32> {
+        let mut widths: Vec<u32> = self.config.widths.iter().map(|&w| w.min(width)).collect();
+        widths.sort_unstable();
+        widths.dedup();
+        widths
+    }
+}
+
+/// Markup for a post or page cover: a `<picture>` if variants were
+/// generated, otherwise the image as given
+pub fn cover_html(cover_image: &str, picture: Option<&Picture>, prefix: &str) -> String {
+    let attrs = [
+        ("class".to_string(), "post-header-image".to_string()),
+        ("alt".to_string(), String::new()),
+        ("fetchpriority".to_string(), "high".to_string()),
+    ];
+    match picture {
+        Some(picture) => picture.html(prefix, COVER_SIZES, &attrs),
+        None => format!(
+            "<img class=\"post-header-image\" src=\"{}\" alt=\"\" />",
+            html_escape(cover_image)
+        ),
+    }
+}
+
+/// `../` once per directory level of a site-relative page path
+fn page_prefix(page_path: &str) -> String {
+    "../".repeat(page_path.matches('/').count())
+}
+
+fn scaled_height(width: u32, height: u32, new_width: u32) -> u32 {
+    let scaled = (height as u64 * new_width as u64 + width as u64 / 2) / width.max(1) as u64;
+    (scaled as u32).max(1)
+}
+
+/// Image dimensions as displayed, read from the header alone
+fn dimensions(bytes: &[u8]) -> ImageResult<(u32, u32)> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    Ok(match decoder.orientation()? {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    })
+}
+
+/// Decodes an image upright, as 8-bit RGB or RGBA
+fn decode(bytes: &[u8]) -> ImageResult<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    })
+}
+
+fn encode(image: &DynamicImage, format: Format, quality: u8) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let result = match format {
+        Format::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut bytes, AVIF_SPEED, quality,
+        )),
+        Format::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality)),
+        Format::Png => image.write_with_encoder(PngEncoder::new(&mut bytes)),
+        Format::Webp => {
+            let (width, height) = (image.width(), image.height());
+            let encoder = match image {
+                DynamicImage::ImageRgba8(rgba) => webp::Encoder::from_rgba(rgba, width, height),
+                _ => webp::Encoder::from_rgb(image.as_bytes(), width, height),
+            };
+            bytes.extend_from_slice(&encoder.encode(quality as f32));
+            Ok(())
+        }
+    };
+    result.map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Attributes of an `<img>` tag, with values ready to write between double
+/// quotes (bare attributes get an empty value). `None` if any part of the
+/// tag isn't an attribute.
+fn parse_attrs(tag: &str) -> Option<Vec<(String, String)>> {
+    let inner = tag.strip_prefix("<img")?.strip_suffix('>')?;
+    // A slash ends the tag only after whitespace or a quote; in `src=a/`
+    // it belongs to the value
+    let mut rest = match inner.strip_suffix('/') {
+        Some(before) if before.ends_with(|c: char| c.is_whitespace() || c == '"' || c == '\'') => {
+            before
+        }
+        _ => inner,
+    };
+    let mut attrs = Vec::new();
+    while let Some(caps) = ATTR_RE.captures(rest) {
+        let value = match (caps.get(2), caps.get(3), caps.get(4)) {
+            (Some(value), _, _) | (_, _, Some(value)) => value.as_str().to_string(),
+            (_, Some(value), _) => value.as_str().replace('"', "&quot;"),
+            _ => String::new(),
+        };
+        attrs.push((caps[1].to_string(), value));
+        rest = &rest[caps[0].len()..];
+    }
+    rest.trim().is_empty().then_some(attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_html() {
+        let root = std::env::temp_dir().join(format!("generator-images-{}", std::process::id()));
+        let paths = SitePaths {
+            content_root: root.join("content"),
+            output_dir: root.join("dist"),
+            cache_dir: None,
+        };
+        fs::create_dir_all(paths.resources_dir()).unwrap();
+        DynamicImage::new_rgb8(40, 20)
+            .save(paths.resources_dir().join("wide.png"))
+            .unwrap();
+        fs::write(paths.resources_dir().join("anim.gif"), GIF).unwrap();
+
+        let config = ImageConfig {
+            widths: vec![16, 64],
+            quality: 50,
+        };
+        let mut cache = BuildCache::load(None, &paths.output_dir, String::new());
+        let mut images = Images::new(&config, &paths, &mut cache);
+        let html = images
+            .rewrite_html(
+                r#"<p><img src="../../resources/wide.png" alt="Wide" /> <img src="../../resources/anim.gif" alt="" /> <img src="https://example.com/a.png" alt="" /></p>"#,
+                "posts/slug/",
+            )
+            .unwrap();
+
+        // Widths are capped at the source's 40px, in AVIF, WebP and PNG
+        assert_eq!(images.generated, 6);
+        let name = |width, extension| {
+            let hash = hash_bytes(&fs::read(paths.resources_dir().join("wide.png")).unwrap());
+            format!("images/wide-{}-{}.{}", &hash[..8], width, extension)
+        };
+        assert!(paths.output_dir.join(name(16, "avif")).is_file());
+        assert!(
+            html.contains(&format!(
+                r#"<source type="image/webp" srcset="../../{} 16w, ../../{} 40w""#,
+                name(16, "webp"),
+                name(40, "webp")
+            )),
+            "{}",
+            html
+        );
+        assert!(html.contains(&format!(
+            r#"<img src="../../{}" srcset="../../{} 16w, ../../{} 40w" sizes="{}" width="40" height="20" alt="Wide" loading="lazy" decoding="async" /></picture>"#,
+            name(40, "png"),
+            name(16, "png"),
+            name(40, "png"),
+            CONTENT_SIZES
+        )), "{}", html);
+        // GIFs keep their animation but still get dimensions
+        assert!(html.contains(r#"<img src="../../resources/anim.gif" alt="" loading="lazy" decoding="async" width="1" height="1" />"#), "{}", html);
+        assert!(
+            html.contains(
+                r#"<img src="https://example.com/a.png" alt="" loading="lazy" decoding="async" />"#
+            ),
+            "{}",
+            html
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_img_attribute_forms_survive_rewriting() {
+        let root = std::env::temp_dir().join(format!("generator-attrs-{}", std::process::id()));
+        let paths = SitePaths {
+            content_root: root.join("content"),
+            output_dir: root.join("dist"),
+            cache_dir: None,
+        };
+        let config = ImageConfig::default();
+        let mut cache = BuildCache::load(None, &paths.output_dir, String::new());
+        let mut images = Images::new(&config, &paths, &mut cache);
+        let mut rewrite = |html: &str| images.rewrite_html(html, "posts/slug/").unwrap();
+
+        // Double-quoted, single-quoted, unquoted and bare attributes
+        assert_eq!(
+            rewrite(r#"<img src="a.png" alt="A">"#),
+            r#"<img src="a.png" alt="A" loading="lazy" decoding="async" />"#
+        );
+        assert_eq!(
+            rewrite(r#"<img src='logo.png' alt='Say "hi"'>"#),
+            r#"<img src="logo.png" alt="Say &quot;hi&quot;" loading="lazy" decoding="async" />"#
+        );
+        assert_eq!(
+            rewrite("<img src=logo.png width=100 alt='Logo'>"),
+            r#"<img src="logo.png" width="100" alt="Logo" loading="lazy" decoding="async" />"#
+        );
+        assert_eq!(
+            rewrite(r#"<img src="map.png" ismap />"#),
+            r#"<img src="map.png" ismap="" loading="lazy" decoding="async" />"#
+        );
+        // Anything else is left exactly as written
+        let odd = r#"<img src="a.png"alt="A">"#;
+        assert_eq!(rewrite(odd), odd);
+    }
+
+    /// A 1×1 GIF
+    const GIF: &[u8] = &[
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+    ];
+}
@@ -0,0 +1,482 @@
+//! Checks the generated site for broken links. Relative `href`s and `src`s
+//! must point at a file in the output tree and `#fragment`s at an element id
+//! in the target page. External URLs only have to be well formed, unless
+//! they are requested too.
+
+use crate::config::SiteConfig;
+use crate::error::{Error, ErrorKind};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+use walkdir::WalkDir;
+
+static TAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[a-zA-Z][^>]*>").unwrap());
+static URL_ATTR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\s(href|src|srcset)="([^"]*)""#).unwrap());
+/// Resource hints name an origin rather than a page, so they aren't followed
+static HINT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\srel="(?:preconnect|dns-prefetch)""#).unwrap());
+static ID_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\sid="([^"]*)""#).unwrap());
+/// `name` is only a fragment target on `<a>`; on `<meta>` and form fields
+/// it means something else
+static ANCHOR_NAME_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"<a\s(?:[^>]*?\s)?name="([^"]*)""#).unwrap());
+/// A URL scheme such as `https:`; a colon after the first `/` is part of a
+/// relative path
+static SCHEME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").unwrap());
+
+/// Schemes that don't point at a page, so there's nothing to check
+const IGNORED_SCHEMES: [&str; 4] = ["mailto:", "tel:", "javascript:", "data:"];
+
+/// Outcome of checking every page in the output directory
+pub struct LinkReport {
+    /// References followed, counting each occurrence
+    pub checked: usize,
+    pub errors: Vec<Error>,
+}
+
+/// Checks every HTML file under `output_dir`. `sources` maps a page's
+/// site-relative URL to the markdown it was rendered from, so problems in
+/// the body are reported against the line that wrote the link. With
+/// `external`, http(s) links are requested as well.
+pub fn check(
+    site: &SiteConfig,
+    output_dir: &Path,
+    sources: &HashMap<String, PathBuf>,
+    external: bool,
+) -> LinkReport {
+    let mut checker = Checker {
+        output_dir,
+        base_url: Url::parse(&site.base_url).ok(),
+        external,
+        ids: HashMap::new(),
+        responses: HashMap::new(),
+        checked: 0,
+    };
+
+    let mut pages: Vec<PathBuf> = WalkDir::new(output_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "html"))
+        .map(|e| e.into_path())
+        .collect();
+    pages.sort();
+
+    let mut errors = Vec::new();
+    // A broken link in a template shows up on every page; report it once
+    let mut template_errors = HashSet::new();
+    for page in pages {
+        let html = match fs::read_to_string(&page) {
+            Ok(html) => html,
+            Err(e) => {
+                errors.push(Error::io(&page, e));
+                continue;
+            }
+        };
+        let page_path = page_path(output_dir, &page);
+        let source = sources
+            .get(&page_path)
+            .and_then(|path| Some((path, fs::read_to_string(path).ok()?)));
+
+        let tags = TAG_RE
+            .find_iter(&html)
+            .filter(|tag| !HINT_RE.is_match(tag.as_str()));
+        for caps in tags.flat_map(|tag| {
+            URL_ATTR_RE
+                .captures_iter(tag.as_str())
+                .map(move |caps| (tag.start(), caps))
+        }) {
+            let (tag_start, caps) = caps;
+            let value = caps.get(2).unwrap();
+            let urls: Vec<String> = match &caps[1] {
+                "srcset" => value
+                    .as_str()
+                    .split(',')
+                    .filter_map(|candidate| candidate.split_whitespace().next())
+                    .map(unescape)
+                    .collect(),
+                _ => vec![unescape(value.as_str())],
+            };
+            for url in urls {
+                let Err(message) = checker.check_url(&url, &page, &page_path) else {
+                    continue;
+                };
+                let error = Error::new(ErrorKind::Link, message);
+                // Point at the markdown that wrote the link, or failing that
+                // (e.g. a link from a template) at the generated page
+                let in_source = source.as_ref().and_then(|(path, markdown)| {
+                    let offset = markdown.find(url.as_str())?;
+                    Some((path, markdown, offset))
+                });
+                match in_source {
+                    Some((path, markdown, offset)) => {
+                        errors.push(error.at_offset(markdown, offset).with_path(path))
+                    }
+                    None if template_errors.insert(error.message.clone()) => errors.push(
+                        error
+                            .at_offset(&html, tag_start + value.start())
+                            .with_path(&page),
+                    ),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    LinkReport {
+        checked: checker.checked,
+        errors,
+    }
+}
+
+struct Checker<'a> {
+    output_dir: &'a Path,
+    /// Links to the site's own absolute URL are checked like relative ones
+    base_url: Option<Url>,
+    external: bool,
+    /// Element ids by page, loaded as fragments are checked
+    ids: HashMap<PathBuf, HashSet<String>>,
+    /// Results of external requests, so each URL is requested once
+    responses: HashMap<String, Result<(), String>>,
+    checked: usize,
+}
+
+impl Checker<'_> {
+    /// Checks one URL found in the page at `page` (site-relative `page_path`)
+    fn check_url(&mut self, url: &str, page: &Path, page_path: &str) -> Result<(), String> {
+        if url.is_empty() || IGNORED_SCHEMES.iter().any(|scheme| url.starts_with(scheme)) {
+            return Ok(());
+        }
+        self.checked += 1;
+
+        let path = match self.internal_path(url) {
+            Some(path) => path,
+            None if url.starts_with("//") || SCHEME_RE.is_match(url) => {
+                return self.check_external(url)
+            }
+            None => url.to_string(),
+        };
+
+        let (target, fragment) = match path.split_once('#') {
+            Some(("", fragment)) => (page.to_path_buf(), Some(fragment)),
+            Some((_, fragment)) => (self.target(url, &path, page_path)?, Some(fragment)),
+            None => (self.target(url, &path, page_path)?, None),
+        };
+        let Some(fragment) = fragment.map(percent_decode).filter(|f| !f.is_empty()) else {
+            return Ok(());
+        };
+        if target.extension().is_some_and(|ext| ext == "html")
+            && !self.ids(&target).contains(&fragment)
+        {
+            return Err(format!(
+                "`{}` links to a missing anchor: no element with id `{}` in {}",
+                url,
+                fragment,
+                self.display(&target)
+            ));
+        }
+        Ok(())
+    }
+
+    /// An absolute URL on this site, rewritten as a root-relative path
+    fn internal_path(&self, url: &str) -> Option<String> {
+        let base = self.base_url.as_ref()?;
+        let path = url.strip_prefix(base.as_str())?;
+        Some(format!("/{}", path))
+    }
+
+    /// The output file a relative or root-relative `path` points at. `url`
+    /// is the link as written, for messages.
+    fn target(&self, url: &str, path: &str, page_path: &str) -> Result<PathBuf, String> {
+        let Some(site_path) = resolve(path, page_path) else {
+            return Err(format!("`{}` points above the site root", url));
+        };
+        let is_dir = path
+            .split(['?', '#'])
+            .next()
+            .is_some_and(|p| p.ends_with('/'));
+        let file = self.output_dir.join(percent_decode(&site_path));
+        let target = if is_dir || file.is_dir() {
+            file.join("index.html")
+        } else {
+            file
+        };
+        if target.is_file() {
+            Ok(target)
+        } else {
+            Err(format!(
+                "`{}` points to a missing file: {}",
+                url,
+                self.display(&target)
+            ))
+        }
+    }
+
+    fn check_external(&mut self, url: &str) -> Result<(), String> {
+        let absolute = match url.strip_prefix("//") {
+            Some(rest) => format!("https://{}", rest),
+            None => url.to_string(),
+        };
+        let parsed =
+            Url::parse(&absolute).map_err(|e| format!("malformed URL `{}`: {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Ok(());
+        }
+        if parsed
+            .host_str()
+            .is_none_or(|host| !host.contains('.') && host != "localhost")
+        {
+            return Err(format!("malformed URL `{}`: no valid host", url));
+        }
+        if !self.external {
+            return Ok(());
+        }
+
+        let mut key = parsed.clone();
+        key.set_fragment(None);
+        self.responses
+            .entry(key.to_string())
+            .or_insert_with_key(|url| request(url))
+            .clone()
+    }
+
+    fn ids(&mut self, page: &Path) -> &HashSet<String> {
+        self.ids.entry(page.to_path_buf()).or_insert_with(|| {
+            let html = fs::read_to_string(page).unwrap_or_default();
+            ID_RE
+                .captures_iter(&html)
+                .chain(ANCHOR_NAME_RE.captures_iter(&html))
+                .map(|caps| unescape(&caps[1]))
+                .collect()
+        })
+    }
+
+    /// Output path relative to the output directory, for messages
+    fn display(&self, path: &Path) -> String {
+        path.strip_prefix(self.output_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+}
+
+/// Requests an external URL, falling back from `HEAD` to `GET` for servers
+/// that don't support it
+fn request(url: &str) -> Result<(), String> {
+    static AGENT: Lazy<ureq::Agent> = Lazy::new(|| {
+        ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(15))
+            .user_agent(concat!("nicolaschan-generator/", env!("CARGO_PKG_VERSION")))
+            .build()
+    });
+
+    let response = match AGENT.head(url).call() {
+        Err(ureq::Error::Status(405 | 403 | 404 | 501, _)) => AGENT.get(url).call(),
+        response => response,
+    };
+    match response {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) => {
+            Err(format!("`{}` responded with HTTP {}", url, status))
+        }
+        Err(ureq::Error::Transport(transport)) => Err(format!(
+            "`{}` is unreachable: {}",
+            url,
+            transport
+                .message()
+                .map_or_else(|| transport.kind().to_string(), str::to_string)
+        )),
+    }
+}
+
+/// Resolves a URL as written in a page at `page_path` (e.g. `posts/slug/`)
+/// to a site-relative path. `None` for external URLs and paths that climb
+/// above the site root.
+pub fn resolve(url: &str, page_path: &str) -> Option<String> {
+    if url.is_empty() || url.starts_with("//") || url.starts_with('#') || SCHEME_RE.is_match(url) {
+        return None;
+    }
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    let (base, url) = match url.strip_prefix('/') {
+        Some(url) => ("", url),
+        None => (page_path, url),
+    };
+
+    let mut segments: Vec<&str> = base.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in url.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Site-relative URL of the directory a page is in, e.g. `posts/slug/`
+fn page_path(output_dir: &Path, page: &Path) -> String {
+    let dir = page
+        .parent()
+        .and_then(|dir| dir.strip_prefix(output_dir).ok())
+        .unwrap_or(Path::new(""));
+    let path: Vec<String> = dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path.join("/"))
+    }
+}
+
+/// Undoes the HTML escaping of an attribute value
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("../../resources/img/a.jpg", "posts/slug/").as_deref(),
+            Some("resources/img/a.jpg")
+        );
+        assert_eq!(
+            resolve("/resources/a.png?v=2", "posts/slug/").as_deref(),
+            Some("resources/a.png")
+        );
+        assert_eq!(
+            resolve("img/a.png", "about/").as_deref(),
+            Some("about/img/a.png")
+        );
+        assert_eq!(resolve("../../a.png", "about/"), None);
+        assert_eq!(resolve("https://example.com/a.png", "about/"), None);
+        assert_eq!(resolve("data:image/png;base64,AAAA", "about/"), None);
+        assert_eq!(
+            resolve("img/12:30.png", "about/").as_deref(),
+            Some("about/img/12:30.png")
+        );
+    }
+
+    #[test]
+    fn test_check_reports_broken_links_against_source() {
+        let root = std::env::temp_dir().join(format!("generator-links-{}", std::process::id()));
+        let output_dir = root.join("dist");
+        let post_dir = output_dir.join("posts/hello");
+        fs::create_dir_all(&post_dir).unwrap();
+        fs::create_dir_all(output_dir.join("about")).unwrap();
+        fs::write(
+            output_dir.join("about/index.html"),
+            r#"<h2 id="contact">Contact</h2>"#,
+        )
+        .unwrap();
+        fs::write(
+            post_dir.join("index.html"),
+            concat!(
+                r#"<link rel="preconnect" href="https://fonts.example.com" />"#,
+                r##"<a href="../../">Home</a><a href="#intro">Intro</a><h2 id="intro">Intro</h2>"##,
+                "\n",
+                r#"<p><a href="../../about/#contact">ok</a> <a href="https://nicolaschan.com/about/#nope">x</a> "#,
+                r#"<img src="../../resources/gone.png" alt="" /> <a href="https://exa mple.com/">y</a> "#,
+                r#"<a href="mailto:me@example.com">z</a></p>"#,
+            ),
+        )
+        .unwrap();
+        fs::write(
+            output_dir.join("index.html"),
+            r#"<a href="posts/hello/">Hello</a>"#,
+        )
+        .unwrap();
+        let markdown = root.join("hello.md");
+        fs::write(
+            &markdown,
+            "---\ntitle: Hello\n---\n\n[ok](../../about/#contact) ![](../../resources/gone.png)\n",
+        )
+        .unwrap();
+        let sources = HashMap::from([("posts/hello/".to_string(), markdown.clone())]);
+
+        let report = check(&SiteConfig::default(), &output_dir, &sources, false);
+
+        assert_eq!(report.checked, 7);
+        let messages: Vec<String> = report.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[..2],
+            [
+                "`https://nicolaschan.com/about/#nope` links to a missing anchor: no element with id `nope` in about/index.html",
+                "`../../resources/gone.png` points to a missing file: resources/gone.png",
+            ],
+        );
+        assert!(messages[2].starts_with("malformed URL `https://exa mple.com/`: "));
+        // Found in the markdown, so reported against it
+        assert_eq!(report.errors[1].path.as_deref(), Some(markdown.as_path()));
+        assert_eq!(report.errors[1].location.map(|l| l.line), Some(5));
+        // Only in the generated page
+        assert_eq!(report.errors[0].path, Some(post_dir.join("index.html")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_only_ids_and_anchor_names_are_fragment_targets() {
+        let root = std::env::temp_dir().join(format!("generator-names-{}", std::process::id()));
+        let output_dir = root.join("dist");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(
+            output_dir.join("index.html"),
+            concat!(
+                r#"<meta name="description" content="A blog" /><a name="top"></a>"#,
+                r##"<a href="#top">Top</a><a href="#description">About</a>"##,
+            ),
+        )
+        .unwrap();
+
+        let report = check(&SiteConfig::default(), &output_dir, &HashMap::new(), false);
+
+        let messages: Vec<&str> = report.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["`#description` links to a missing anchor: no element with id `description` in index.html"]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
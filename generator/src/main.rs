@@ -1,104 +1,165 @@
+mod archive;
+mod build;
+mod cache;
+mod card;
+mod cli;
 mod config;
+mod error;
+mod highlight;
+mod images;
+mod links;
+mod markdown;
+mod math;
+mod meta;
+mod page;
+mod paginate;
 mod post;
+mod reading;
+mod related;
+mod scaffold;
+mod search;
+mod series;
+mod serve;
+mod sitemap;
+mod tags;
 mod template;
+mod watch;
 
+use build::{BuildOptions, SitePaths};
+use clap::Parser;
+use cli::{Cli, Command, SiteArgs, EXIT_CONFIG_ERROR, EXIT_CONTENT_ERROR};
 use config::SiteConfig;
-use post::Post;
-use std::fs;
-use std::path::Path;
-use walkdir::WalkDir;
+use error::Error;
+use serve::LiveReload;
+use std::process;
+use std::sync::Arc;
 
 fn main() {
-    let site = SiteConfig::default();
-    
-    let content_dir = Path::new("../content/posts");
-    let pages_dir = Path::new("../content/pages");
-    let resources_dir = Path::new("../content/resources");
-    let output_dir = Path::new("../dist");
+    let cli = Cli::parse();
 
-    // Create output directories
-    fs::create_dir_all(output_dir.join("posts")).expect("Failed to create output directory");
-    fs::create_dir_all(output_dir.join("resources")).expect("Failed to create resources directory");
+    let paths = cli.site.paths().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(EXIT_CONFIG_ERROR);
+    });
 
-    // Copy resources (images, etc.)
-    if resources_dir.exists() {
-        for entry in WalkDir::new(resources_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-        {
-            let relative_path = entry.path().strip_prefix(resources_dir).unwrap();
-            let dest_path = output_dir.join("resources").join(relative_path);
-            
-            // Create parent directories if needed
-            if let Some(parent) = dest_path.parent() {
-                fs::create_dir_all(parent).expect("Failed to create resource subdirectory");
+    match cli.command.unwrap_or(Command::Build) {
+        Command::Build => {
+            let site = load_site(&cli.site, &paths);
+            let report = match build::build(&site, &paths, &cli.site.build_options()) {
+                Ok(report) => report,
+                Err(errors) => {
+                    report_errors(&errors);
+                    eprintln!("\n✗ Build failed with {} error(s)", errors.len());
+                    process::exit(EXIT_CONTENT_ERROR);
+                }
+            };
+            report_errors(&report.skipped);
+            report_errors(&report.warnings);
+            if !report.skipped.is_empty() {
+                eprintln!("\n⚠ Skipped {} file(s) with errors", report.skipped.len());
+            }
+            if !report.warnings.is_empty() {
+                eprintln!("\n⚠ {} warning(s)", report.warnings.len());
             }
-            
-            fs::copy(entry.path(), &dest_path).expect("Failed to copy resource");
-            println!("Copied: {}", dest_path.display());
+            println!(
+                "\n✓ Generated {} posts ({} files unchanged)",
+                report.posts, report.unchanged
+            );
         }
-    }
+        Command::Serve { host, port } => {
+            // Config is reloaded on every rebuild so site.toml edits show up live.
+            // A broken post shouldn't take the whole preview down, so serve
+            // always keeps going and shows the errors in the browser instead.
+            let rebuild = {
+                let args = cli.site;
+                let paths = paths.clone();
+                move || match args.site_config(&paths) {
+                    Ok(mut site) => {
+                        site.live_reload = true;
+                        let options = BuildOptions {
+                            keep_going: true,
+                            ..args.build_options()
+                        };
+                        let errors = match build::build(&site, &paths, &options) {
+                            Ok(report) => {
+                                report_errors(&report.warnings);
+                                report.skipped
+                            }
+                            Err(errors) => errors,
+                        };
+                        errors.iter().map(Error::render).collect()
+                    }
+                    Err(e) => vec![e],
+                }
+            };
 
-    // Collect all posts
-    let mut posts: Vec<Post> = Vec::new();
+            let reload = Arc::new(LiveReload::new());
+            let errors = rebuild();
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            reload.publish(errors);
 
-    if content_dir.exists() {
-        for entry in WalkDir::new(content_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "md"))
-        {
-            let content = fs::read_to_string(entry.path()).expect("Failed to read file");
-            let filename = entry.path().file_name().and_then(|n| n.to_str()).unwrap_or("");
-            match Post::from_markdown(&content, filename) {
-                Ok(post) => {
-                    // Generate individual post HTML in slug/index.html for clean URLs
-                    let post_html = template::render_post(&site, &post);
-                    let post_dir = output_dir.join("posts").join(&post.slug);
-                    fs::create_dir_all(&post_dir).expect("Failed to create post directory");
-                    let post_path = post_dir.join("index.html");
-                    fs::write(&post_path, post_html).expect("Failed to write post");
-                    println!("Generated: {}", post_path.display());
-                    posts.push(post);
+            let result = watch::spawn(
+                paths.content_root.clone(),
+                paths.output_dir.clone(),
+                rebuild,
+                reload.clone(),
+            )
+            .and_then(|()| serve::serve(&paths.output_dir, &host, port, &reload));
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                process::exit(EXIT_CONFIG_ERROR);
+            }
+        }
+        Command::New { title, slug, page } => {
+            match scaffold::new_content(&paths, &title, slug.as_deref(), page) {
+                Ok(path) => println!("Created: {}", path.display()),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(EXIT_CONTENT_ERROR);
                 }
-                Err(e) => eprintln!("Error parsing {}: {}", entry.path().display(), e),
             }
         }
-    }
-
-    // Sort posts by date (newest first), only posts with dates
-    posts.sort_by(|a, b| b.date.cmp(&a.date));
-
-    // Generate index page
-    let index_html = template::render_index(&site, &posts);
-    let index_path = output_dir.join("index.html");
-    fs::write(&index_path, index_html).expect("Failed to write index");
-    println!("Generated: {}", index_path.display());
-
-    // Generate pages from markdown
-    if pages_dir.exists() {
-        for entry in WalkDir::new(pages_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "md"))
-        {
-            let content = fs::read_to_string(entry.path()).expect("Failed to read file");
-            let filename = entry.path().file_name().and_then(|n| n.to_str()).unwrap_or("");
-            match Post::from_markdown(&content, filename) {
-                Ok(page) => {
-                    // Generate page in slug/index.html for clean URLs
-                    let page_html = template::render_page(&site, &page);
-                    let page_dir = output_dir.join(&page.slug);
-                    fs::create_dir_all(&page_dir).expect("Failed to create page directory");
-                    let page_path = page_dir.join("index.html");
-                    fs::write(&page_path, page_html).expect("Failed to write page");
-                    println!("Generated: {}", page_path.display());
+        Command::Check { external } => {
+            let site = load_site(&cli.site, &paths);
+            let report = match build::build(&site, &paths, &cli.site.build_options()) {
+                Ok(report) => report,
+                Err(errors) => {
+                    report_errors(&errors);
+                    eprintln!("\n✗ {} file(s) have errors", errors.len());
+                    process::exit(EXIT_CONTENT_ERROR);
                 }
-                Err(e) => eprintln!("Error parsing {}: {}", entry.path().display(), e),
+            };
+            let links = links::check(&site, &paths.output_dir, &report.sources, external);
+            report_errors(&report.skipped);
+            report_errors(&report.warnings);
+            report_errors(&links.errors);
+
+            let problems = report.skipped.len() + links.errors.len();
+            if problems > 0 {
+                eprintln!("\n✗ Check found {} problem(s)", problems);
+                process::exit(EXIT_CONTENT_ERROR);
             }
+            println!(
+                "\n✓ {} posts OK, {} links checked",
+                report.posts, links.checked
+            );
         }
     }
+}
+
+/// Loads the site config, exiting with a config error if it is invalid
+fn load_site(args: &SiteArgs, paths: &SitePaths) -> SiteConfig {
+    args.site_config(paths).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(EXIT_CONFIG_ERROR);
+    })
+}
 
-    println!("\n✓ Generated {} posts", posts.len());
+/// Prints errors rustc-style, separated by blank lines
+fn report_errors(errors: &[Error]) {
+    for error in errors {
+        eprintln!("{}\n", error.render());
+    }
 }
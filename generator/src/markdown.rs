@@ -0,0 +1,551 @@
+//! The markdown pipeline shared by posts and pages: frontmatter splitting
+//! and parsing, math, heading anchors, tables, syntax highlighting and
+//! excerpts. Content types only decide which frontmatter fields they accept.
+
+use crate::error::{Error, ErrorKind};
+use crate::highlight;
+use crate::math::{self, MathError};
+use crate::reading::{self, ReadingStats};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
+use once_cell::sync::Lazy;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use regex::Regex;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Serialize};
+
+/// Paragraph that marks where the table of contents goes
+const TOC_MARKER: &str = "<p>[[toc]]</p>";
+
+/// A frontmatter date or time, with the offset it was written in. Plain
+/// dates are midnight UTC.
+pub type Timestamp = DateTime<FixedOffset>;
+
+// Regex to match markdown links: [text](url)
+static LINK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\]]+)\]\([^)]+\)").unwrap());
+
+/// A markdown file split into its frontmatter and body. Errors from any
+/// step are located relative to the whole file; the caller attaches the path.
+pub struct Document<'a> {
+    source: &'a str,
+    frontmatter: &'a str,
+    body: &'a str,
+}
+
+/// HTML rendered from a document body
+pub struct Rendered {
+    pub html: String,
+    /// Whether the body contains math, so pages only load KaTeX's CSS when needed
+    pub has_math: bool,
+    /// Headings nested by level
+    pub toc: Vec<TocEntry>,
+    /// Whether a `[[toc]]` marker in the body was replaced with the TOC
+    pub has_toc_marker: bool,
+    /// Length of the prose, leaving out code blocks and math
+    pub reading: ReadingStats,
+}
+
+/// A heading in the table of contents, with the headings nested under it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    /// The heading's content as HTML, with any math rendered
+    pub html: String,
+    /// The heading's `id`, as used by its anchor link
+    pub slug: String,
+    pub children: Vec<TocEntry>,
+}
+
+impl<'a> Document<'a> {
+    pub fn parse(source: &'a str) -> Result<Self, Error> {
+        let (frontmatter, body) = split_frontmatter(source)?;
+        Ok(Document {
+            source,
+            frontmatter,
+            body,
+        })
+    }
+
+    /// Deserializes the YAML frontmatter into a content type's fields. In
+    /// strict mode, keys the type doesn't have are an error rather than
+    /// ignored, which catches typos like `tag:`.
+    pub fn frontmatter<T: DeserializeOwned>(&self, strict: bool) -> Result<T, Error> {
+        let offset = offset_in(self.source, self.frontmatter);
+        let parsed: T = serde_yaml::from_str(self.frontmatter).map_err(|e| {
+            let error = Error::new(ErrorKind::Yaml, yaml_message(&e));
+            match e.location() {
+                Some(loc) => error.at_offset(self.source, offset + loc.index()),
+                None => error.at_offset(self.source, offset),
+            }
+        })?;
+        if strict {
+            self.check_keys(field_names::<T>())?;
+        }
+        Ok(parsed)
+    }
+
+    /// Fails on the first top-level frontmatter key not in `fields`
+    fn check_keys(&self, fields: &[&str]) -> Result<(), Error> {
+        let Ok(mapping) = serde_yaml::from_str::<serde_yaml::Mapping>(self.frontmatter) else {
+            return Ok(());
+        };
+        let Some(key) = mapping
+            .keys()
+            .filter_map(|key| key.as_str())
+            .find(|key| !fields.contains(key))
+        else {
+            return Ok(());
+        };
+        let expected: Vec<String> = fields.iter().map(|field| format!("`{}`", field)).collect();
+        let offset = offset_in(self.source, self.frontmatter)
+            + key_offset(self.frontmatter, key).unwrap_or(0);
+        Err(Error::new(
+            ErrorKind::Yaml,
+            format!(
+                "unknown field `{}`, expected one of {}",
+                key,
+                expected.join(", ")
+            ),
+        )
+        .at_offset(self.source, offset))
+    }
+
+    /// Parses a `YYYY-MM-DD` date or an RFC 3339 timestamp such as
+    /// `2024-03-01T09:30:00+01:00`, pointing errors at `key`
+    pub fn date(&self, key: &str, value: Option<&str>) -> Result<Option<Timestamp>, Error> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(Some(timestamp));
+        }
+        match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => Ok(Some(date.and_time(NaiveTime::MIN).and_utc().fixed_offset())),
+            Err(e) => {
+                let offset = offset_in(self.source, self.frontmatter)
+                    + value_offset(self.frontmatter, key).unwrap_or(0);
+                let message = format!(
+                    "invalid `{}`: {}; expected YYYY-MM-DD or an RFC 3339 timestamp",
+                    key, e
+                );
+                Err(Error::new(ErrorKind::Date, message).at_offset(self.source, offset))
+            }
+        }
+    }
+
+    /// Fails on a tag or series name (`what`) under `key` with no letters
+    /// or digits: its slug would be empty, so its page would land on the
+    /// index of all tags or series
+    pub fn check_slugs<'v>(
+        &self,
+        key: &str,
+        what: &str,
+        names: impl IntoIterator<Item = &'v String>,
+    ) -> Result<(), Error> {
+        let Some(name) = names.into_iter().find(|name| slugify(name).is_empty()) else {
+            return Ok(());
+        };
+        let offset = offset_in(self.source, self.frontmatter)
+            + value_offset(self.frontmatter, key).unwrap_or(0);
+        Err(Error::new(
+            ErrorKind::Frontmatter,
+            format!("{} `{}` needs at least one letter or digit for its URL", what, name),
+        )
+        .at_offset(self.source, offset))
+    }
+
+    /// Renders the body to HTML
+    pub fn render(&self) -> Result<Rendered, Error> {
+        let body_offset = offset_in(self.source, self.body);
+        markdown_to_html(self.body).map_err(|e| {
+            Error::new(ErrorKind::Math, e.message).at_offset(self.source, body_offset + e.offset)
+        })
+    }
+
+    /// Plain-text summary from the first paragraph of the body
+    pub fn excerpt(&self, max_chars: usize) -> String {
+        generate_excerpt(self.body, max_chars)
+    }
+}
+
+/// Slug for a file without one in its frontmatter: the filename minus `.md`
+pub fn default_slug(filename: &str) -> String {
+    filename.strip_suffix(".md").unwrap_or(filename).to_string()
+}
+
+fn split_frontmatter(content: &str) -> Result<(&str, &str), Error> {
+    let start = offset_in(content, content.trim_start());
+    let content_start = &content[start..];
+
+    if !content_start.starts_with("---") {
+        return Err(Error::new(
+            ErrorKind::Frontmatter,
+            "missing frontmatter: expected the file to start with `---`",
+        )
+        .at_offset(content, start));
+    }
+
+    let after_first = &content_start[3..];
+    let end_pos = after_first.find("\n---").ok_or_else(|| {
+        Error::new(
+            ErrorKind::Frontmatter,
+            "unterminated frontmatter: no closing `---` found",
+        )
+        .at_offset(content, start)
+    })?;
+
+    let frontmatter = after_first[..end_pos].trim();
+    let markdown = after_first[end_pos + 4..].trim();
+
+    Ok((frontmatter, markdown))
+}
+
+/// Renders markdown to HTML, collecting headings for the table of contents.
+/// Math is pulled out before markdown parsing so emphasis and smart
+/// punctuation can't mangle it, then rendered to static HTML by KaTeX.
+fn markdown_to_html(markdown: &str) -> Result<Rendered, MathError> {
+    use std::collections::HashMap;
+
+    let (markdown, math) = math::extract(markdown);
+    let rendered_math = math::render(&math)?;
+
+    let options = Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_SMART_PUNCTUATION;
+
+    let parser = Parser::new_ext(&markdown, options);
+    
+    // Process events, adding syntax highlighting to code blocks and wrapping tables
+    let mut in_code_block = false;
+    let mut code_lang = String::new();
+    let mut code_content = String::new();
+    let mut in_heading = false;
+    let mut heading_level = 0;
+    let mut heading_text = String::new();
+    let mut heading_slugs: HashMap<String, usize> = HashMap::new();
+    let mut headings: Vec<TocEntry> = Vec::new();
+    let mut events: Vec<Event> = Vec::new();
+    let mut prose = String::new();
+
+    for event in parser {
+        // Collect the visible text for the word count
+        match &event {
+            Event::Text(text) if !in_code_block => prose.push_str(&math::strip_placeholders(text)),
+            Event::Code(code) => prose.push_str(code),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => prose.push(' '),
+            _ => {}
+        }
+
+        match event {
+            Event::Start(Tag::Heading(level, _, _)) => {
+                in_heading = true;
+                heading_level = match level {
+                    pulldown_cmark::HeadingLevel::H1 => 1,
+                    pulldown_cmark::HeadingLevel::H2 => 2,
+                    pulldown_cmark::HeadingLevel::H3 => 3,
+                    pulldown_cmark::HeadingLevel::H4 => 4,
+                    pulldown_cmark::HeadingLevel::H5 => 5,
+                    pulldown_cmark::HeadingLevel::H6 => 6,
+                };
+                heading_text.clear();
+            }
+            Event::End(Tag::Heading(_, _, _)) => {
+                in_heading = false;
+                let base_slug = slugify(&math::strip_placeholders(&heading_text));
+                let count = heading_slugs.entry(base_slug.clone()).or_insert(0);
+                let slug = if *count == 0 {
+                    base_slug.clone()
+                } else {
+                    format!("{}-{}", base_slug, count)
+                };
+                *heading_slugs.get_mut(&base_slug).unwrap() += 1;
+                let heading_html = format!(
+                    "<h{level} id=\"{slug}\"><a class=\"heading-link\" href=\"#{slug}\">{text}</a></h{level}>",
+                    level = heading_level,
+                    slug = slug,
+                    text = html_escape(&heading_text)
+                );
+                events.push(Event::Html(heading_html.into()));
+                headings.push(TocEntry {
+                    level: heading_level,
+                    html: math::substitute(&html_escape(&heading_text), &rendered_math),
+                    slug,
+                    children: Vec::new(),
+                });
+            }
+            Event::Text(ref text) if in_heading => {
+                heading_text.push_str(text);
+            }
+            Event::Code(ref code) if in_heading => {
+                heading_text.push_str(code);
+            }
+            Event::Start(Tag::Table(_)) => {
+                events.push(Event::Html("<div class=\"table-wrapper\">".into()));
+                events.push(event);
+            }
+            Event::End(Tag::Table(_)) => {
+                events.push(event);
+                events.push(Event::Html("</div>".into()));
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                in_code_block = true;
+                code_lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_content.clear();
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                in_code_block = false;
+                
+                // Apply syntax highlighting
+                let highlighted = highlight::code_block(&code_content, &code_lang);
+                events.push(Event::Html(highlighted.into()));
+                
+                code_lang.clear();
+                code_content.clear();
+            }
+            Event::Text(text) if in_code_block => {
+                code_content.push_str(&text);
+            }
+            _ => {
+                events.push(event);
+            }
+        }
+    }
+
+    let mut html_output = String::new();
+    pulldown_cmark::html::push_html(&mut html_output, events.into_iter());
+
+    let toc = nest_headings(headings);
+    let has_toc_marker = html_output.contains(TOC_MARKER);
+    if has_toc_marker {
+        html_output = html_output.replacen(TOC_MARKER, &toc_html(&toc), 1);
+    }
+
+    Ok(Rendered {
+        html: math::substitute(&html_output, &rendered_math),
+        has_math: !math.is_empty(),
+        toc,
+        has_toc_marker,
+        reading: reading::stats(&prose),
+    })
+}
+
+/// Turns headings in document order into a tree, nesting each heading under
+/// the closest preceding heading of a higher level
+fn nest_headings(headings: Vec<TocEntry>) -> Vec<TocEntry> {
+    let mut roots: Vec<TocEntry> = Vec::new();
+    for heading in headings {
+        let mut siblings = &mut roots;
+        while siblings.last().is_some_and(|last| last.level < heading.level) {
+            siblings = &mut siblings.last_mut().unwrap().children;
+        }
+        siblings.push(heading);
+    }
+    roots
+}
+
+/// Renders the table of contents as nested lists of heading links
+pub fn toc_html(toc: &[TocEntry]) -> String {
+    fn push_list(entries: &[TocEntry], html: &mut String) {
+        html.push_str("<ol>");
+        for entry in entries {
+            html.push_str(&format!(
+                "<li><a href=\"#{}\">{}</a>",
+                entry.slug, entry.html
+            ));
+            if !entry.children.is_empty() {
+                push_list(&entry.children, html);
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ol>");
+    }
+
+    let mut html = String::from(
+        "<nav class=\"toc\" aria-label=\"Table of contents\"><p class=\"toc-title\">Contents</p>",
+    );
+    push_list(toc, &mut html);
+    html.push_str("</nav>");
+    html
+}
+
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>()
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn generate_excerpt(markdown: &str, max_chars: usize) -> String {
+    // Find the first paragraph (text before a blank line, heading, or list)
+    let first_paragraph: String = markdown
+        .lines()
+        .take_while(|line| {
+            let trimmed = line.trim();
+            !trimmed.is_empty() 
+                && !trimmed.starts_with('#') 
+                && !trimmed.starts_with("```")
+                && !trimmed.starts_with('-')
+                && !trimmed.starts_with('*')
+                && !trimmed.starts_with("1.")
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    
+    // Replace markdown links [text](url) with just text
+    let text = LINK_RE.replace_all(&first_paragraph, "$1").to_string();
+    
+    // Remove remaining markdown syntax characters
+    let text: String = text
+        .chars()
+        .filter(|c| !['*', '_', '`'].contains(c))
+        .collect();
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if text.len() <= max_chars {
+        text
+    } else {
+        let truncated: String = text.chars().take(max_chars).collect();
+        if let Some(last_space) = truncated.rfind(' ') {
+            format!("{}…", &truncated[..last_space])
+        } else {
+            format!("{}…", truncated)
+        }
+    }
+}
+
+/// Byte offset of `slice` within `source`; `slice` must borrow from `source`
+fn offset_in(source: &str, slice: &str) -> usize {
+    slice.as_ptr() as usize - source.as_ptr() as usize
+}
+
+/// Offset of a top-level `key:` line in YAML frontmatter
+fn key_offset(frontmatter: &str, key: &str) -> Option<usize> {
+    let mut line_start = 0;
+    for line in frontmatter.split_inclusive('\n') {
+        if line.strip_prefix(key).is_some_and(|rest| rest.starts_with(':')) {
+            return Some(line_start);
+        }
+        line_start += line.len();
+    }
+    None
+}
+
+/// Names of the fields a frontmatter type accepts, as its `Deserialize`
+/// implementation reports them to serde
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for Fields<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only the field names are needed"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Fields(&mut fields));
+    fields
+}
+
+/// Offset of the value of a top-level `key:` line in YAML frontmatter
+pub fn value_offset(frontmatter: &str, key: &str) -> Option<usize> {
+    let mut line_start = 0;
+    for line in frontmatter.split_inclusive('\n') {
+        if let Some(rest) = line.strip_prefix(key).and_then(|r| r.strip_prefix(':')) {
+            let value = rest.trim_start();
+            return Some(line_start + line.len() - rest.len() + (rest.len() - value.len()));
+        }
+        line_start += line.len();
+    }
+    None
+}
+
+/// serde_yaml's message without the trailing "at line X column Y", which is
+/// reported separately against the whole file
+fn yaml_message(error: &serde_yaml::Error) -> String {
+    static LOCATION_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r" at line \d+ column \d+").unwrap());
+    LOCATION_RE.replace_all(&error.to_string(), "").into_owned()
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_headings_get_unique_anchors() {
+        let document = Document::parse("---\ntitle: T\n---\n\n## Setup\n\n## Setup\n").unwrap();
+        let html = document.render().unwrap().html;
+        assert!(html.contains(r#"<h2 id="setup">"#), "{}", html);
+        assert!(html.contains(r#"<h2 id="setup-1">"#), "{}", html);
+    }
+
+    #[test]
+    fn test_toc_nests_headings_and_replaces_marker() {
+        let source = "---\ntitle: T\n---\n\n[[toc]]\n\n## A\n\n### A1\n\n#### A1a\n\n### A2\n\n## B\n";
+        let rendered = Document::parse(source).unwrap().render().unwrap();
+
+        let outline: Vec<(&str, Vec<&str>)> = rendered
+            .toc
+            .iter()
+            .map(|e| (e.slug.as_str(), e.children.iter().map(|c| c.slug.as_str()).collect()))
+            .collect();
+        assert_eq!(outline, vec![("a", vec!["a1", "a2"]), ("b", vec![])]);
+        assert_eq!(rendered.toc[0].children[0].children[0].slug, "a1a");
+
+        assert!(rendered.has_toc_marker);
+        assert!(!rendered.html.contains("[[toc]]"));
+        assert!(rendered.html.starts_with(r#"<nav class="toc""#), "{}", rendered.html);
+    }
+
+    #[test]
+    fn test_heading_with_math() {
+        let source = "---\ntitle: T\n---\n\n## Solving $x^2$ quickly\n";
+        let rendered = Document::parse(source).unwrap().render().unwrap();
+
+        let entry = &rendered.toc[0];
+        assert_eq!(entry.slug, "solving-quickly");
+        assert!(entry.html.starts_with("Solving <span class=\"katex\">"), "{}", entry.html);
+        assert!(!entry.html.contains('\u{E000}'), "{}", entry.html);
+        assert!(toc_html(&rendered.toc).contains("katex"));
+        assert!(rendered.html.contains(r#"<h2 id="solving-quickly">"#), "{}", rendered.html);
+    }
+}
@@ -0,0 +1,305 @@
+use once_cell::sync::Lazy;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use regex::Regex;
+use std::ops::Range;
+
+// Placeholders use private-use characters so markdown processing (emphasis,
+// smart punctuation, escaping) passes them through untouched
+const OPEN: char = '\u{E000}';
+const CLOSE: char = '\u{E001}';
+
+static PLACEHOLDER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("\u{E000}([0-9]+)\u{E001}").unwrap());
+
+/// A TeX expression pulled out of the markdown source
+#[derive(Debug, PartialEq)]
+pub struct Math {
+    pub tex: String,
+    pub display: bool,
+    /// Byte offset of the opening `$` in the markdown
+    pub offset: usize,
+}
+
+/// A math expression KaTeX couldn't render
+#[derive(Debug)]
+pub struct MathError {
+    /// Byte offset of the expression in the markdown
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Replaces `$...$` (inline) and `$$...$$` (display) math with placeholders
+/// before markdown sees it. Code blocks and inline code spans are left
+/// alone, as is `\$`.
+pub fn extract(markdown: &str) -> (String, Vec<Math>) {
+    let mut output = String::with_capacity(markdown.len());
+    let mut math = Vec::new();
+    let mut prose = String::new();
+    let mut prose_start = 0;
+    let mut fence: Option<String> = None;
+    let mut line_start = 0;
+    let indented = indented_code(markdown);
+
+    for line in markdown.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        let trimmed = line.trim_start();
+        match &fence {
+            Some(marker) => {
+                output.push_str(line);
+                if is_closing_fence(trimmed.trim_end(), marker) {
+                    fence = None;
+                }
+            }
+            None => {
+                if indented.iter().any(|code| code.start < line_start && offset < code.end) {
+                    extract_from_prose(&prose, prose_start, &mut output, &mut math);
+                    prose.clear();
+                    output.push_str(line);
+                } else if let Some(marker) = fence_marker(trimmed) {
+                    extract_from_prose(&prose, prose_start, &mut output, &mut math);
+                    prose.clear();
+                    output.push_str(line);
+                    fence = Some(marker);
+                } else {
+                    if prose.is_empty() {
+                        prose_start = offset;
+                    }
+                    prose.push_str(line);
+                }
+            }
+        }
+    }
+    extract_from_prose(&prose, prose_start, &mut output, &mut math);
+
+    (output, math)
+}
+
+/// Byte ranges of indented code blocks. Whether an indented line is code
+/// depends on the lines around it (it may continue a paragraph or list
+/// item), so this asks the markdown parser.
+fn indented_code(markdown: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, Options::ENABLE_FOOTNOTES)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The run of backticks or tildes opening a fenced code block, if any
+fn fence_marker(line: &str) -> Option<String> {
+    let first = line.chars().next()?;
+    if first != '`' && first != '~' {
+        return None;
+    }
+    let marker: String = line.chars().take_while(|&c| c == first).collect();
+    (marker.len() >= 3).then_some(marker)
+}
+
+/// A closing fence uses the same character, at least as many times
+fn is_closing_fence(line: &str, marker: &str) -> bool {
+    let c = marker.chars().next().unwrap_or('`');
+    line.len() >= marker.len() && line.chars().all(|x| x == c)
+}
+
+/// Extracts math from a run of non-code lines starting at byte `base` of the
+/// markdown
+fn extract_from_prose(text: &str, base: usize, output: &mut String, math: &mut Vec<Math>) {
+    let (offsets, chars): (Vec<usize>, Vec<char>) = text.char_indices().unzip();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1) == Some(&'$') => {
+                output.push_str("\\$");
+                i += 2;
+            }
+            '`' => {
+                // Copy code spans verbatim, matching the opening run length
+                let run = count_run(&chars, i, '`');
+                let end = find_backtick_run(&chars, i + run, run).unwrap_or(i + run);
+                output.extend(&chars[i..end]);
+                i = end;
+            }
+            '$' if chars.get(i + 1) == Some(&'$') => match find_display_end(&chars, i + 2) {
+                Some(end) => {
+                    push_math(&chars[i + 2..end], true, base + offsets[i], output, math);
+                    i = end + 2;
+                }
+                None => {
+                    output.push_str("$$");
+                    i += 2;
+                }
+            },
+            '$' => match find_inline_end(&chars, i + 1) {
+                Some(end) => {
+                    push_math(&chars[i + 1..end], false, base + offsets[i], output, math);
+                    i = end + 1;
+                }
+                None => {
+                    output.push('$');
+                    i += 1;
+                }
+            },
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn push_math(
+    tex: &[char],
+    display: bool,
+    offset: usize,
+    output: &mut String,
+    math: &mut Vec<Math>,
+) {
+    output.push(OPEN);
+    output.push_str(&math.len().to_string());
+    output.push(CLOSE);
+    math.push(Math {
+        tex: tex.iter().collect::<String>().trim().to_string(),
+        display,
+        offset,
+    });
+}
+
+fn count_run(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().take_while(|&&x| x == c).count()
+}
+
+/// Index just past the closing backtick run of exactly `len` backticks
+fn find_backtick_run(chars: &[char], mut i: usize, len: usize) -> Option<usize> {
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let run = count_run(chars, i, '`');
+            if run == len {
+                return Some(i + run);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+fn find_display_end(chars: &[char], start: usize) -> Option<usize> {
+    (start..chars.len().saturating_sub(1))
+        .find(|&j| chars[j] == '$' && chars[j + 1] == '$' && chars[j - 1] != '\\')
+        .filter(|&j| j > start)
+}
+
+/// Closing `$` for inline math, using pandoc's rules so prices like
+/// "$5 and $10" stay text: the opening `$` must not be followed by
+/// whitespace, the closing one must not follow whitespace or precede a
+/// digit, and the math can't cross a blank line.
+fn find_inline_end(chars: &[char], start: usize) -> Option<usize> {
+    if chars.get(start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+    let mut j = start;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 2,
+            '\n' if chars.get(j + 1) == Some(&'\n') => return None,
+            '`' => return None,
+            '$' => {
+                let closes = !chars[j - 1].is_whitespace()
+                    && !chars.get(j + 1).is_some_and(|c| c.is_ascii_digit());
+                if closes {
+                    return Some(j);
+                }
+                j += 1;
+            }
+            _ => j += 1,
+        }
+    }
+    None
+}
+
+/// Renders extracted math to static HTML + MathML with KaTeX
+pub fn render(math: &[Math]) -> Result<Vec<String>, MathError> {
+    math.iter()
+        .map(|m| {
+            let opts = katex::Opts::builder()
+                .display_mode(m.display)
+                .throw_on_error(true)
+                .build()
+                .map_err(|e| e.to_string());
+            opts.and_then(|opts| katex::render_with_opts(&m.tex, &opts).map_err(|e| e.to_string()))
+                .map_err(|message| MathError {
+                    offset: m.offset,
+                    message: format!("can't render `{}`: {}", m.tex, katex_message(&message)),
+                })
+        })
+        .collect()
+}
+
+/// Pulls KaTeX's own parse error out of the JS exception wrapper
+fn katex_message(error: &str) -> String {
+    static PARSE_ERROR_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new("KaTeX parse error: ([^\\\\\"]*)").unwrap());
+    match PARSE_ERROR_RE.captures(error) {
+        Some(caps) => caps[1].trim_end_matches(&[' ', ':'][..]).to_string(),
+        None => error.to_string(),
+    }
+}
+
+/// Removes placeholders from text, e.g. before counting words
+pub fn strip_placeholders(text: &str) -> std::borrow::Cow<'_, str> {
+    PLACEHOLDER_RE.replace_all(text, " ")
+}
+
+/// Puts rendered math back in place of the placeholders
+pub fn substitute(html: &str, rendered: &[String]) -> String {
+    PLACEHOLDER_RE
+        .replace_all(html, |caps: &regex::Captures| {
+            caps[1]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| rendered.get(i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_skips_code_and_prices() {
+        let markdown = "If you have $N$ keys, that's $N \\times M$ copies.\n\
+                        It costs $5 and $10. Use `$HOME` here.\n\n\
+                        ```sh\necho \"$first_host\"\n```\n\n$$\na^2 + b^2\n$$\n";
+        let (text, math) = extract(markdown);
+
+        assert_eq!(
+            math,
+            vec![
+                Math { tex: "N".to_string(), display: false, offset: 12 },
+                Math { tex: "N \\times M".to_string(), display: false, offset: 29 },
+                Math { tex: "a^2 + b^2".to_string(), display: true, offset: 120 },
+            ]
+        );
+        assert!(text.contains("$5 and $10"));
+        assert!(text.contains("`$HOME`"));
+        assert!(text.contains("echo \"$first_host\""));
+    }
+
+    #[test]
+    fn test_extract_skips_indented_code() {
+        let markdown = "Costs $x$:\n\n    price=$a$ + $b$\n\n- item\n\n    continued with $y$\n";
+        let (text, math) = extract(markdown);
+
+        let tex: Vec<&str> = math.iter().map(|m| m.tex.as_str()).collect();
+        assert_eq!(tex, ["x", "y"]);
+        assert!(text.contains("    price=$a$ + $b$\n"), "{}", text);
+    }
+}
@@ -0,0 +1,281 @@
+//! Link preview and search engine metadata for each page: the canonical URL,
+//! OpenGraph and Twitter card tags and schema.org JSON-LD.
+
+use crate::card;
+use crate::config::SiteConfig;
+use crate::images::Picture;
+use crate::links;
+use crate::page::Page;
+use crate::post::Post;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Frontmatter `meta:` block replacing what link previews show for a post
+/// or page
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetaOverrides {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Preview image, resolved like `cover_image`; defaults to the cover
+    pub image: Option<String>,
+}
+
+/// Metadata rendered into a page's `<head>`
+pub struct Meta {
+    pub title: String,
+    pub description: String,
+    /// Absolute URL of the page
+    pub canonical_url: String,
+    /// OpenGraph type: `website` or `article`
+    pub kind: &'static str,
+    pub image: Option<MetaImage>,
+    /// RFC 3339 timestamps for articles
+    pub published_time: Option<String>,
+    pub modified_time: Option<String>,
+    pub tags: Vec<String>,
+    /// Name of an article's author
+    pub author: Option<String>,
+    /// schema.org description of the page, ready to embed in a `<script>`
+    pub json_ld: Option<String>,
+}
+
+/// An absolute image URL with its dimensions, if known
+pub struct MetaImage {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl Meta {
+    /// Metadata for a listing or utility page at a site-relative URL
+    pub fn website(site: &SiteConfig, url_path: &str, title: String, description: String) -> Self {
+        Meta {
+            title,
+            description,
+            canonical_url: site.absolute_url(url_path),
+            kind: "website",
+            image: None,
+            published_time: None,
+            modified_time: None,
+            tags: Vec::new(),
+            author: None,
+            json_ld: None,
+        }
+    }
+
+    /// Metadata for the home page, describing the site as a whole. The
+    /// search action lets search engines offer a search box for the site.
+    pub fn home(site: &SiteConfig, url_path: &str, title: String) -> Self {
+        let mut meta = Meta::website(site, url_path, title, site.description.clone());
+        meta.json_ld = Some(script_json(&json!({
+            "@context": "https://schema.org",
+            "@type": "WebSite",
+            "name": site.name,
+            "description": site.description,
+            "url": site.absolute_url(""),
+            "inLanguage": site.language,
+            "author": author(site, None),
+            "potentialAction": {
+                "@type": "SearchAction",
+                "target": format!("{}?q={{search_term_string}}", site.absolute_url("search/")),
+                "query-input": "required name=search_term_string",
+            },
+        })));
+        meta
+    }
+
+    pub fn post(site: &SiteConfig, post: &Post) -> Self {
+        let url_path = post.url_path();
+        let overrides = &post.meta;
+        let title = overrides
+            .title
+            .clone()
+            .unwrap_or_else(|| post.title.clone());
+        let description = overrides
+            .description
+            .clone()
+            .unwrap_or_else(|| post.excerpt.clone());
+        let image = preview_image(
+            site,
+            &url_path,
+            overrides,
+            post.cover_image.as_deref(),
+            post.cover.as_ref(),
+        )
+        // Posts with neither get a generated card
+        .or_else(|| {
+            card::wants_card(&site.cards, post).then(|| MetaImage {
+                url: site.absolute_url(&format!("{}{}", url_path, card::FILE_NAME)),
+                width: Some(card::WIDTH),
+                height: Some(card::HEIGHT),
+            })
+        });
+        let published_time = post.date.map(|d| d.to_rfc3339());
+        let modified_time = post.updated.map(|d| d.to_rfc3339());
+        // Cross-posts point search engines at the original
+        let canonical_url = match &post.canonical_url {
+            Some(url) => absolute_url(site, url, &url_path),
+            None => site.absolute_url(&url_path),
+        };
+
+        let mut schema = Map::new();
+        schema.insert("@context".into(), "https://schema.org".into());
+        schema.insert("@type".into(), "BlogPosting".into());
+        schema.insert("headline".into(), title.clone().into());
+        schema.insert("description".into(), description.clone().into());
+        schema.insert("url".into(), canonical_url.clone().into());
+        schema.insert(
+            "mainEntityOfPage".into(),
+            json!({ "@type": "WebPage", "@id": canonical_url }),
+        );
+        schema.insert("author".into(), author(site, post.author.as_deref()));
+        schema.insert("inLanguage".into(), site.language.clone().into());
+        if let Some(time) = &published_time {
+            schema.insert("datePublished".into(), time.clone().into());
+        }
+        if let Some(time) = modified_time.as_ref().or(published_time.as_ref()) {
+            schema.insert("dateModified".into(), time.clone().into());
+        }
+        if let Some(image) = &image {
+            schema.insert("image".into(), image.url.clone().into());
+        }
+        if !post.tags.is_empty() {
+            schema.insert("keywords".into(), post.tags.clone().into());
+        }
+        schema.insert("wordCount".into(), post.word_count.into());
+        schema.insert("timeRequired".into(), post.iso8601_reading_time().into());
+
+        Meta {
+            title,
+            description,
+            canonical_url,
+            kind: "article",
+            image,
+            published_time,
+            modified_time,
+            tags: post.tags.clone(),
+            author: Some(site.author_name(post.author.as_deref()).to_string()),
+            json_ld: Some(script_json(&Value::Object(schema))),
+        }
+    }
+
+    pub fn page(site: &SiteConfig, page: &Page) -> Self {
+        let url_path = page.url_path();
+        let overrides = &page.meta;
+        let title = overrides
+            .title
+            .clone()
+            .unwrap_or_else(|| page.title.clone());
+        let description = overrides
+            .description
+            .clone()
+            .unwrap_or_else(|| page.description.clone());
+        let mut meta = Meta::website(site, &url_path, title, description);
+        meta.image = preview_image(
+            site,
+            &url_path,
+            overrides,
+            page.cover_image.as_deref(),
+            page.cover.as_ref(),
+        );
+        meta
+    }
+
+    /// Twitter card layout: a large image when there is one to show
+    pub fn twitter_card(&self) -> &'static str {
+        match self.image {
+            Some(_) => "summary_large_image",
+            None => "summary",
+        }
+    }
+}
+
+/// A post's author from the site config, or else the site author
+fn author(site: &SiteConfig, id: Option<&str>) -> Value {
+    match id.and_then(|id| site.find_author(id)) {
+        Some(author) => {
+            let mut person = json!({ "@type": "Person", "name": author.name });
+            if let Some(url) = &author.url {
+                person["url"] = url.clone().into();
+            }
+            person
+        }
+        None => json!({ "@type": "Person", "name": site.author, "url": site.absolute_url("") }),
+    }
+}
+
+/// The override image, or else the cover. Cover variants are used when
+/// there are some, since their size is known and the original may be huge.
+fn preview_image(
+    site: &SiteConfig,
+    page_path: &str,
+    overrides: &MetaOverrides,
+    cover_image: Option<&str>,
+    cover: Option<&Picture>,
+) -> Option<MetaImage> {
+    if let Some(image) = &overrides.image {
+        return Some(MetaImage {
+            url: absolute_url(site, image, page_path),
+            width: None,
+            height: None,
+        });
+    }
+    if let Some(picture) = cover {
+        return Some(MetaImage {
+            url: site.absolute_url(&picture.largest_fallback().path),
+            width: Some(picture.width),
+            height: Some(picture.height),
+        });
+    }
+    cover_image.map(|image| MetaImage {
+        url: absolute_url(site, image, page_path),
+        width: None,
+        height: None,
+    })
+}
+
+/// A URL as written on the page at `page_path`, made absolute
+fn absolute_url(site: &SiteConfig, url: &str, page_path: &str) -> String {
+    match links::resolve(url, page_path) {
+        Some(path) => site.absolute_url(&path),
+        None => url.to_string(),
+    }
+}
+
+/// JSON that can't end the `<script>` element it's embedded in
+fn script_json(value: &Value) -> String {
+    value.to_string().replace("</", "<\\/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_meta_uses_overrides_and_absolute_urls() {
+        let content = "---\ntitle: Lights\ndate: 2024-03-01\ntags: [home]\n\
+                       cover_image: ../../resources/img/lights.jpg\n\
+                       meta:\n  description: Blinking </script> lights\n---\n\nBody\n";
+        let post = Post::from_markdown(content, "lights.md", false).unwrap();
+        let meta = Meta::post(&SiteConfig::default(), &post);
+
+        assert_eq!(meta.canonical_url, "https://nicolaschan.com/posts/lights/");
+        assert_eq!(meta.description, "Blinking </script> lights");
+        assert_eq!(
+            meta.image.unwrap().url,
+            "https://nicolaschan.com/resources/img/lights.jpg"
+        );
+        assert_eq!(
+            meta.published_time.as_deref(),
+            Some("2024-03-01T00:00:00+00:00")
+        );
+        let json_ld = meta.json_ld.unwrap();
+        assert!(json_ld.contains(r#""@type":"BlogPosting""#), "{}", json_ld);
+        assert!(
+            json_ld.contains(r#"Blinking <\/script> lights"#),
+            "{}",
+            json_ld
+        );
+    }
+}
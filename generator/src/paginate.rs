@@ -0,0 +1,79 @@
+/// One page of a paginated list. The first page lives at the list's own URL
+/// and later ones under `page/N/` beneath it.
+pub struct Pager<'a, T> {
+    pub items: &'a [T],
+    /// One-based page number
+    pub number: usize,
+    /// Number of pages in the list
+    pub total: usize,
+    /// Site-relative URL of the first page, e.g. `` or `tags/rust/`
+    base: String,
+}
+
+impl<T> Pager<'_, T> {
+    /// Site-relative URL of this page
+    pub fn url_path(&self) -> String {
+        self.path_of(self.number)
+    }
+
+    /// The page before this one, holding newer posts
+    pub fn prev_path(&self) -> Option<String> {
+        (self.number > 1).then(|| self.path_of(self.number - 1))
+    }
+
+    /// The page after this one, holding older posts
+    pub fn next_path(&self) -> Option<String> {
+        (self.number < self.total).then(|| self.path_of(self.number + 1))
+    }
+
+    fn path_of(&self, number: usize) -> String {
+        match number {
+            1 => self.base.clone(),
+            n => format!("{}page/{}/", self.base, n),
+        }
+    }
+}
+
+/// Splits `items` into pages of `per_page` under the site-relative URL
+/// `base`. An empty list still gets a (single, empty) page.
+pub fn paginate<'a, T>(items: &'a [T], per_page: usize, base: &str) -> Vec<Pager<'a, T>> {
+    let chunks: Vec<&[T]> = if items.is_empty() {
+        vec![items]
+    } else {
+        items.chunks(per_page.max(1)).collect()
+    };
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, items)| Pager {
+            items,
+            number: i + 1,
+            total,
+            base: base.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_links_pages() {
+        let items = [1, 2, 3, 4, 5];
+        let pages = paginate(&items, 2, "tags/rust/");
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].url_path(), "tags/rust/");
+        assert_eq!(pages[0].prev_path(), None);
+        assert_eq!(pages[1].url_path(), "tags/rust/page/2/");
+        assert_eq!(pages[1].prev_path().as_deref(), Some("tags/rust/"));
+        assert_eq!(pages[1].next_path().as_deref(), Some("tags/rust/page/3/"));
+        assert_eq!(pages[2].items, [5]);
+        assert_eq!(pages[2].next_path(), None);
+
+        let empty: [u8; 0] = [];
+        assert_eq!(paginate(&empty, 2, "").len(), 1);
+    }
+}
@@ -1,31 +1,46 @@
+use crate::error::Error;
+use crate::images::{self, Picture};
+use crate::markdown::{self, Document, Timestamp, TocEntry};
+use crate::meta::MetaOverrides;
 use chrono::NaiveDate;
-use once_cell::sync::Lazy;
-use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
-use regex::Regex;
-use serde::Deserialize;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{ThemeSet, Theme};
-use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
-
-static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
-static THEME: Lazy<Theme> = Lazy::new(|| {
-    let theme_set = ThemeSet::load_defaults();
-    theme_set.themes["base16-ocean.dark"].clone()
-});
-
-// Regex to match markdown links: [text](url)
-static LINK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\]]+)\]\([^)]+\)").unwrap());
-
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Post {
     pub title: String,
     pub slug: String,
-    pub date: Option<NaiveDate>,
+    pub date: Option<Timestamp>,
+    /// When the post was last revised, if after `date`
+    pub updated: Option<Timestamp>,
+    /// Id of an author from the site config; defaults to the site author
+    pub author: Option<String>,
+    /// Where the post was first published, if it's cross-posted
+    pub canonical_url: Option<String>,
     pub excerpt: String,
     pub tags: Vec<String>,
+    /// Name of the series the post is a part of
+    pub series: Option<String>,
+    /// Position in the series; parts without one are ordered by date
+    pub series_order: Option<u32>,
     pub cover_image: Option<String>,
+    /// Responsive versions of `cover_image`, filled in by the image pipeline
+    #[serde(default)]
+    pub cover: Option<Picture>,
     pub content_html: String,
+    /// Drafts are only built with `--drafts`
+    pub draft: bool,
+    /// Scheduled publication date; defaults to `date`
+    pub publish_at: Option<Timestamp>,
+    pub has_math: bool,
+    pub toc: Vec<TocEntry>,
+    /// Words in the body, excluding code and math
+    pub word_count: usize,
+    /// Estimated reading time in minutes
+    pub reading_minutes: u32,
+    pub meta: MetaOverrides,
+    /// Show the table of contents above the body. Set by `toc: true`, unless
+    /// a `[[toc]]` marker already placed it inside the body.
+    pub show_toc: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,358 +48,180 @@ struct Frontmatter {
     title: String,
     slug: Option<String>,
     date: Option<String>,
+    updated: Option<String>,
+    author: Option<String>,
+    canonical_url: Option<String>,
     excerpt: Option<String>,
     #[serde(default)]
     tags: Vec<String>,
+    series: Option<String>,
+    series_order: Option<u32>,
     cover_image: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    publish_at: Option<String>,
+    #[serde(default)]
+    toc: bool,
+    #[serde(default)]
+    meta: MetaOverrides,
 }
 
 impl Post {
-    pub fn from_markdown(content: &str, filename: &str) -> Result<Self, String> {
-        // Split frontmatter and content
-        let (frontmatter_str, markdown) = Self::split_frontmatter(content)?;
-
-        // Parse frontmatter
-        let frontmatter: Frontmatter =
-            serde_yaml::from_str(frontmatter_str).map_err(|e| format!("YAML error: {}", e))?;
-
-        // Parse date if provided
-        let date = match frontmatter.date {
-            Some(date_str) => Some(
-                NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
-                    .map_err(|e| format!("Date parse error: {}", e))?
-            ),
-            None => None,
-        };
-
-        // Convert markdown to HTML
-        let content_html = Self::markdown_to_html(markdown);
+    /// Parses a markdown file with YAML frontmatter. Error locations are
+    /// relative to `content`; the caller attaches the file path. `strict`
+    /// rejects unknown frontmatter keys.
+    pub fn from_markdown(content: &str, filename: &str, strict: bool) -> Result<Self, Error> {
+        let document = Document::parse(content)?;
+        let frontmatter: Frontmatter = document.frontmatter(strict)?;
+
+        let date = document.date("date", frontmatter.date.as_deref())?;
+        let updated = document.date("updated", frontmatter.updated.as_deref())?;
+        let publish_at = document.date("publish_at", frontmatter.publish_at.as_deref())?;
+        document.check_slugs("tags", "tag", &frontmatter.tags)?;
+        document.check_slugs("series", "series", &frontmatter.series)?;
+
+        let rendered = document.render()?;
 
         // Generate excerpt if not provided
-        let excerpt = frontmatter.excerpt.unwrap_or_else(|| {
-            Self::generate_excerpt(markdown, 160)
-        });
+        let excerpt = frontmatter
+            .excerpt
+            .unwrap_or_else(|| document.excerpt(160));
 
         // Use slug from frontmatter, or default to filename without .md extension
-        let slug = frontmatter.slug.unwrap_or_else(|| {
-            filename.strip_suffix(".md").unwrap_or(filename).to_string()
-        });
+        let slug = frontmatter
+            .slug
+            .unwrap_or_else(|| markdown::default_slug(filename));
 
         Ok(Post {
             title: frontmatter.title,
             slug,
             date,
+            updated,
+            author: frontmatter.author,
+            canonical_url: frontmatter.canonical_url,
             excerpt,
             tags: frontmatter.tags,
+            series: frontmatter.series,
+            series_order: frontmatter.series_order,
             cover_image: frontmatter.cover_image,
-            content_html,
+            cover: None,
+            content_html: rendered.html,
+            draft: frontmatter.draft,
+            publish_at,
+            has_math: rendered.has_math,
+            word_count: rendered.reading.words,
+            reading_minutes: rendered.reading.minutes,
+            show_toc: frontmatter.toc && !rendered.has_toc_marker && !rendered.toc.is_empty(),
+            toc: rendered.toc,
+            meta: frontmatter.meta,
         })
     }
 
-    fn split_frontmatter(content: &str) -> Result<(&str, &str), String> {
-        let content = content.trim_start();
+    pub fn formatted_date(&self) -> String {
+        self.date.map(|d| d.format("%B %d, %Y").to_string()).unwrap_or_default()
+    }
 
-        if !content.starts_with("---") {
-            return Err("Missing frontmatter delimiter".to_string());
-        }
+    /// Day and month without the year, e.g. "Mar 02", for archive lists
+    pub fn short_date(&self) -> String {
+        self.date.map(|d| d.format("%b %d").to_string()).unwrap_or_default()
+    }
 
-        let after_first = &content[3..];
-        let end_pos = after_first
-            .find("\n---")
-            .ok_or("Missing closing frontmatter delimiter")?;
+    pub fn has_date(&self) -> bool {
+        self.date.is_some()
+    }
 
-        let frontmatter = &after_first[..end_pos].trim();
-        let markdown = &after_first[end_pos + 4..].trim();
+    /// Revision date for the "Updated on" note, when it's a later day than
+    /// `date`
+    pub fn formatted_updated(&self) -> Option<String> {
+        let updated = self.updated?;
+        let later = self.date.is_none_or(|d| updated.date_naive() > d.date_naive());
+        later.then(|| updated.format("%B %d, %Y").to_string())
+    }
 
-        Ok((frontmatter, markdown))
+    /// Most recent of `updated` and `date`, for feeds
+    pub fn modified(&self) -> Option<Timestamp> {
+        self.updated.max(self.date)
     }
 
-    fn markdown_to_html(markdown: &str) -> String {
-        use std::collections::HashMap;
-        
-        let options = Options::ENABLE_STRIKETHROUGH
-            | Options::ENABLE_TABLES
-            | Options::ENABLE_FOOTNOTES
-            | Options::ENABLE_SMART_PUNCTUATION;
-
-        let parser = Parser::new_ext(markdown, options);
-        
-        // Process events, adding syntax highlighting to code blocks and wrapping tables
-        let mut in_code_block = false;
-        let mut code_lang = String::new();
-        let mut code_content = String::new();
-        let mut in_heading = false;
-        let mut heading_level = 0;
-        let mut heading_text = String::new();
-        let mut heading_slugs: HashMap<String, usize> = HashMap::new();
-        let mut events: Vec<Event> = Vec::new();
-
-        for event in parser {
-            match event {
-                Event::Start(Tag::Heading(level, _, _)) => {
-                    in_heading = true;
-                    heading_level = match level {
-                        pulldown_cmark::HeadingLevel::H1 => 1,
-                        pulldown_cmark::HeadingLevel::H2 => 2,
-                        pulldown_cmark::HeadingLevel::H3 => 3,
-                        pulldown_cmark::HeadingLevel::H4 => 4,
-                        pulldown_cmark::HeadingLevel::H5 => 5,
-                        pulldown_cmark::HeadingLevel::H6 => 6,
-                    };
-                    heading_text.clear();
-                }
-                Event::End(Tag::Heading(_, _, _)) => {
-                    in_heading = false;
-                    let base_slug = Self::slugify(&heading_text);
-                    let count = heading_slugs.entry(base_slug.clone()).or_insert(0);
-                    let slug = if *count == 0 {
-                        base_slug
-                    } else {
-                        format!("{}-{}", base_slug, count)
-                    };
-                    *heading_slugs.get_mut(&Self::slugify(&heading_text)).unwrap() += 1;
-                    let heading_html = format!(
-                        "<h{level} id=\"{slug}\"><a class=\"heading-link\" href=\"#{slug}\">{text}</a></h{level}>",
-                        level = heading_level,
-                        slug = slug,
-                        text = html_escape(&heading_text)
-                    );
-                    events.push(Event::Html(heading_html.into()));
-                }
-                Event::Text(ref text) if in_heading => {
-                    heading_text.push_str(text);
-                }
-                Event::Code(ref code) if in_heading => {
-                    heading_text.push_str(code);
-                }
-                Event::Start(Tag::Table(_)) => {
-                    events.push(Event::Html("<div class=\"table-wrapper\">".into()));
-                    events.push(event);
-                }
-                Event::End(Tag::Table(_)) => {
-                    events.push(event);
-                    events.push(Event::Html("</div>".into()));
-                }
-                Event::Start(Tag::CodeBlock(kind)) => {
-                    in_code_block = true;
-                    code_lang = match kind {
-                        CodeBlockKind::Fenced(lang) => lang.to_string(),
-                        CodeBlockKind::Indented => String::new(),
-                    };
-                    code_content.clear();
-                }
-                Event::End(Tag::CodeBlock(_)) => {
-                    in_code_block = false;
-                    
-                    // Apply syntax highlighting
-                    let highlighted = Self::highlight_code(&code_content, &code_lang);
-                    events.push(Event::Html(highlighted.into()));
-                    
-                    code_lang.clear();
-                    code_content.clear();
-                }
-                Event::Text(text) if in_code_block => {
-                    code_content.push_str(&text);
-                }
-                _ => {
-                    events.push(event);
-                }
-            }
-        }
-
-        let mut html_output = String::new();
-        pulldown_cmark::html::push_html(&mut html_output, events.into_iter());
-
-        html_output
+    /// Day of the most recent of `updated` and `date`, for sitemaps
+    pub fn last_modified(&self) -> Option<NaiveDate> {
+        self.modified().map(|d| d.date_naive())
     }
 
-    fn slugify(text: &str) -> String {
-        text.to_lowercase()
-            .chars()
-            .map(|c| {
-                if c.is_alphanumeric() {
-                    c
-                } else if c.is_whitespace() || c == '-' || c == '_' {
-                    '-'
-                } else {
-                    '-'
-                }
-            })
-            .collect::<String>()
-            .split('-')
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<_>>()
-            .join("-")
+    /// Date the post goes live: `publish_at` if set, otherwise `date`
+    pub fn publication_date(&self) -> Option<Timestamp> {
+        self.publish_at.or(self.date)
     }
 
-    fn highlight_code(code: &str, lang_info: &str) -> String {
-        // Parse language and optional filename from info string
-        let (lang, filename) = Self::parse_code_info(lang_info);
-        
-        // Find the syntax for the language
-        let syntax = SYNTAX_SET
-            .find_syntax_by_token(&lang)
-            .or_else(|| SYNTAX_SET.find_syntax_by_extension(&lang))
-            .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
-
-        let mut highlighter = HighlightLines::new(syntax, &THEME);
-        let mut html_output = String::new();
-        
-        for line in LinesWithEndings::from(code) {
-            match highlighter.highlight_line(line, &SYNTAX_SET) {
-                Ok(ranges) => {
-                    let html_line = syntect::html::styled_line_to_highlighted_html(
-                        &ranges[..],
-                        syntect::html::IncludeBackground::No,
-                    ).unwrap_or_else(|_| html_escape(line));
-                    html_output.push_str(&html_line);
-                }
-                Err(_) => {
-                    html_output.push_str(&html_escape(line));
-                }
-            }
-        }
-
-        let lang_class = if lang.is_empty() { 
-            String::new() 
-        } else { 
-            format!(" class=\"language-{}\"", lang) 
-        };
-
-        // If we have a language or filename, wrap in a code-block container with header
-        if !lang.is_empty() || filename.is_some() {
-            let header = Self::build_code_header(&lang, filename.as_deref());
-            format!(
-                "<div class=\"code-block\">{}<pre{}><code>{}</code></pre></div>",
-                header, lang_class, html_output
-            )
-        } else {
-            format!("<div class=\"code-block\">{}<pre{}><code>{}</code></pre></div>", Self::build_code_header("", None), lang_class, html_output)
-        }
+    /// Whether the post is scheduled to go live after `today`
+    pub fn is_scheduled_after(&self, today: NaiveDate) -> bool {
+        self.publication_date().is_some_and(|d| d.date_naive() > today)
     }
 
-    fn parse_code_info(info: &str) -> (String, Option<String>) {
-        let info = info.trim();
-        
-        if info.is_empty() {
-            return (String::new(), None);
-        }
-
-        // Check for "lang:filename" format
-        if let Some((lang, filename)) = info.split_once(':') {
-            let lang = lang.trim().to_string();
-            let filename = filename.trim();
-            if !filename.is_empty() {
-                return (lang, Some(filename.to_string()));
-            }
-            return (lang, None);
-        }
-
-        // Check for "lang filename=path" format
-        if let Some((lang, rest)) = info.split_once(' ') {
-            let rest = rest.trim();
-            if let Some(filename) = rest.strip_prefix("filename=") {
-                return (lang.to_string(), Some(filename.to_string()));
-            }
-        }
-
-        // Just the language
-        (info.split_whitespace().next().unwrap_or("").to_string(), None)
+    /// Publication date for the "scheduled" banner
+    pub fn formatted_publication_date(&self) -> String {
+        self.publication_date()
+            .map(|d| d.format("%B %d, %Y").to_string())
+            .unwrap_or_default()
     }
 
-    fn build_code_header(lang: &str, filename: Option<&str>) -> String {
-        let copy_button = r#"<button class="code-block-copy" title="Copy code"><svg class="copy-icon" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"><rect x="9" y="9" width="13" height="13" rx="2" ry="2"></rect><path d="M5 15H4a2 2 0 0 1-2-2V4a2 2 0 0 1 2-2h9a2 2 0 0 1 2 2v1"></path></svg><svg class="check-icon" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"><polyline points="20 6 9 17 4 12"></polyline></svg></button>"#;
-        
-        match (lang.is_empty(), filename) {
-            (true, None) => {
-                format!(
-                    "<div class=\"code-block-header\"><div class=\"code-block-header-content\"></div>{}</div>",
-                    copy_button
-                )
-            }
-            (false, None) => {
-                format!(
-                    "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-lang\">{}</span></div>{}</div>",
-                    html_escape(lang),
-                    copy_button
-                )
-            }
-            (true, Some(f)) => {
-                format!(
-                    "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-filename\">{}</span></div>{}</div>",
-                    html_escape(f),
-                    copy_button
-                )
-            }
-            (false, Some(f)) => {
-                format!(
-                    "<div class=\"code-block-header\"><div class=\"code-block-header-content\"><span class=\"code-block-lang\">{}</span><span class=\"separator\">·</span><span class=\"code-block-filename\">{}</span></div>{}</div>",
-                    html_escape(lang),
-                    html_escape(f),
-                    copy_button
-                )
-            }
-        }
+    /// Reading time for display, e.g. "5 min read"
+    pub fn formatted_reading_time(&self) -> String {
+        format!("{} min read", self.reading_minutes.max(1))
     }
 
-    fn generate_excerpt(markdown: &str, max_chars: usize) -> String {
-        // Find the first paragraph (text before a blank line, heading, or list)
-        let first_paragraph: String = markdown
-            .lines()
-            .take_while(|line| {
-                let trimmed = line.trim();
-                !trimmed.is_empty() 
-                    && !trimmed.starts_with('#') 
-                    && !trimmed.starts_with("```")
-                    && !trimmed.starts_with('-')
-                    && !trimmed.starts_with('*')
-                    && !trimmed.starts_with("1.")
-            })
-            .collect::<Vec<_>>()
-            .join(" ");
-        
-        // Replace markdown links [text](url) with just text
-        let text = LINK_RE.replace_all(&first_paragraph, "$1").to_string();
-        
-        // Remove remaining markdown syntax characters
-        let text: String = text
-            .chars()
-            .filter(|c| !['*', '_', '`'].contains(c))
-            .collect();
-
-        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
-
-        if text.len() <= max_chars {
-            text
-        } else {
-            let truncated: String = text.chars().take(max_chars).collect();
-            if let Some(last_space) = truncated.rfind(' ') {
-                format!("{}…", &truncated[..last_space])
-            } else {
-                format!("{}…", truncated)
-            }
-        }
+    /// Reading time as an ISO 8601 duration (e.g. `PT5M`), for schema.org
+    /// `timeRequired` in feeds
+    pub fn iso8601_reading_time(&self) -> String {
+        format!("PT{}M", self.reading_minutes.max(1))
     }
 
-    pub fn formatted_date(&self) -> String {
-        self.date.map(|d| d.format("%B %d, %Y").to_string()).unwrap_or_default()
+    /// The cover as seen from a page `prefix` below the site root
+    pub fn cover_html(&self, prefix: &str) -> String {
+        let cover_image = self.cover_image.as_deref().unwrap_or_default();
+        images::cover_html(cover_image, self.cover.as_ref(), prefix)
     }
 
-    pub fn has_date(&self) -> bool {
-        self.date.is_some()
+    /// The table of contents as a nested list of links
+    pub fn toc_html(&self) -> String {
+        markdown::toc_html(&self.toc)
+    }
+
+    /// Site-relative URL of the rendered post
+    pub fn url_path(&self) -> String {
+        format!("posts/{}/", self.slug)
+    }
+
+    /// Date as an RFC 3339 timestamp, for Atom feeds
+    pub fn rfc3339_date(&self) -> String {
+        self.date.map(|d| d.to_rfc3339()).unwrap_or_default()
+    }
+
+    /// Last revision as an RFC 3339 timestamp, for Atom's `<updated>`
+    pub fn rfc3339_modified(&self) -> String {
+        self.modified().map(|d| d.to_rfc3339()).unwrap_or_default()
+    }
+
+    /// Date as an RFC 2822 timestamp, for RSS feeds
+    pub fn rfc2822_date(&self) -> String {
+        self.date.map(|d| d.to_rfc2822()).unwrap_or_default()
     }
 }
 
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
+#[cfg(test)]
+impl Post {
+    /// A post titled and named after `slug`, for tests of the pages built
+    /// from several posts. `frontmatter` holds any further YAML lines.
+    pub fn for_test(slug: &str, frontmatter: &str, body: &str) -> Post {
+        let content = format!("---\ntitle: {}\n{}\n---\n\n{}\n", slug, frontmatter, body);
+        Post::from_markdown(&content, &format!("{}.md", slug), false).unwrap()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorKind;
 
     #[test]
     fn test_parse_post() {
@@ -401,11 +238,64 @@ tags:
 This is a test post with **bold** text.
 "#;
 
-        let post = Post::from_markdown(content, "test-post.md").unwrap();
+        let post = Post::from_markdown(content, "test-post.md", false).unwrap();
         assert_eq!(post.title, "Test Post");
         assert_eq!(post.slug, "test-post");
         assert!(post.date.is_some());
         assert_eq!(post.tags, vec!["rust", "testing"]);
         assert!(post.content_html.contains("<strong>bold</strong>"));
     }
+
+    #[test]
+    fn test_draft_and_scheduled() {
+        let content = "---\ntitle: Soon\ndate: 2026-01-01\npublish_at: 2026-02-01\ndraft: true\n---\n\nBody\n";
+        let post = Post::from_markdown(content, "soon.md", false).unwrap();
+        assert!(post.draft);
+        assert_eq!(
+            post.publication_date().map(|d| d.date_naive()),
+            NaiveDate::from_ymd_opt(2026, 2, 1)
+        );
+        assert!(post.is_scheduled_after(NaiveDate::from_ymd_opt(2026, 1, 15).unwrap()));
+        assert!(!post.is_scheduled_after(NaiveDate::from_ymd_opt(2026, 2, 1).unwrap()));
+    }
+
+    #[test]
+    fn test_timestamps_and_strict_frontmatter() {
+        let content = "---\ntitle: Later\ndate: 2024-03-01T23:30:00-08:00\n\
+                       updated: 2024-03-05\nauthor: sam\n---\n\nBody\n";
+        let post = Post::from_markdown(content, "later.md", true).unwrap();
+        assert_eq!(post.rfc3339_date(), "2024-03-01T23:30:00-08:00");
+        assert_eq!(post.formatted_date(), "March 01, 2024");
+        assert_eq!(post.rfc3339_modified(), "2024-03-05T00:00:00+00:00");
+        assert_eq!(post.formatted_updated().as_deref(), Some("March 05, 2024"));
+        assert_eq!(post.author.as_deref(), Some("sam"));
+
+        let typo = "---\ntitle: Typo\ntag: [rust]\n---\n\nBody\n";
+        assert!(Post::from_markdown(typo, "typo.md", false).is_ok());
+        let err = Post::from_markdown(typo, "typo.md", true).unwrap_err();
+        assert!(
+            err.message.starts_with("unknown field `tag`, expected one of `title`"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.location.map(|l| l.line), Some(3));
+    }
+
+    #[test]
+    fn test_tag_without_a_slug_is_rejected() {
+        let content = "---\ntitle: Loud\ntags: [rust, \"!!!\"]\n---\n\nBody\n";
+        let err = Post::from_markdown(content, "loud.md", false).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Frontmatter);
+        assert!(err.message.starts_with("tag `!!!`"), "{}", err.message);
+        assert_eq!(err.location.map(|l| l.line), Some(3));
+    }
+
+    #[test]
+    fn test_series_without_a_slug_is_rejected() {
+        let content = "---\ntitle: Dash\nseries: \"—\"\n---\n\nBody\n";
+        let err = Post::from_markdown(content, "dash.md", false).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Frontmatter);
+        assert!(err.message.starts_with("series `—`"), "{}", err.message);
+        assert_eq!(err.location.map(|l| l.line), Some(3));
+    }
 }
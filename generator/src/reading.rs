@@ -0,0 +1,70 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Typical reading speed for languages that separate words with spaces
+const WORDS_PER_MINUTE: f64 = 230.0;
+/// Chinese and Japanese are read by character rather than by word
+const CJK_CHARS_PER_MINUTE: f64 = 500.0;
+
+/// Length of a piece of prose
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadingStats {
+    /// Words, counting each Chinese or Japanese character as one word
+    pub words: usize,
+    /// Estimated reading time, rounded up
+    pub minutes: u32,
+}
+
+/// Counts words using Unicode word boundaries, so punctuation, emoji and
+/// CJK text without spaces are handled correctly
+pub fn stats(text: &str) -> ReadingStats {
+    let mut words = 0;
+    let mut cjk = 0;
+    for word in text.unicode_words() {
+        // UAX #29 splits Han and hiragana into single characters but keeps
+        // katakana runs together, so count characters rather than segments
+        let chars = word.chars().filter(|&c| is_cjk(c)).count();
+        if chars > 0 {
+            cjk += chars;
+        } else {
+            words += 1;
+        }
+    }
+
+    let minutes = words as f64 / WORDS_PER_MINUTE + cjk as f64 / CJK_CHARS_PER_MINUTE;
+    ReadingStats {
+        words: words + cjk,
+        minutes: minutes.ceil() as u32,
+    }
+}
+
+/// Han ideographs and Japanese kana. Hangul is written with spaces and is
+/// counted like other scripts.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'     // Hiragana and Katakana
+        | '\u{31F0}'..='\u{31FF}'   // Katakana phonetic extensions
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{FF66}'..='\u{FF9F}'   // Halfwidth Katakana
+        | '\u{20000}'..='\u{2FA1F}' // Supplementary ideographs
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_handles_cjk_and_emoji() {
+        assert_eq!(stats("Hello, world! 👋 It's a well-known fact.").words, 7);
+        assert_eq!(stats("👩‍💻 🎉🎉").words, 0);
+        assert_eq!(stats("我喜欢写代码").words, 6);
+        assert_eq!(stats("Rust で書いた").words, 5);
+        assert_eq!(stats("コンピューター").words, 7);
+        assert_eq!(stats("").minutes, 0);
+
+        let long = "word ".repeat(500) + &"字".repeat(500);
+        assert_eq!(stats(&long), ReadingStats { words: 1000, minutes: 4 });
+    }
+}
@@ -0,0 +1,168 @@
+//! Posts suggested after each post: the most similar posts by shared tags
+//! and TF-IDF similarity of their text, plus the neighbouring posts by date.
+
+use crate::markdown;
+use crate::post::Post;
+use crate::search;
+use std::collections::{HashMap, HashSet};
+
+/// What a shared tag adds to a pair's score
+const TAG_WEIGHT: f64 = 1.0;
+/// What identical text would add; cosine similarity is between 0 and 1
+const TEXT_WEIGHT: f64 = 2.0;
+
+/// Where a post points readers next
+pub struct Related<'a> {
+    /// Most similar posts first
+    pub posts: Vec<&'a Post>,
+    /// The next post by date
+    pub newer: Option<&'a Post>,
+    /// The previous post by date
+    pub older: Option<&'a Post>,
+}
+
+impl Related<'_> {
+    /// Every post linked from the page, whose cards change with them
+    pub fn fingerprint(&self) -> String {
+        let linked = self.posts.iter().chain(&self.newer).chain(&self.older);
+        linked
+            .map(|post| serde_json::to_string(post).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Up to `count` related posts for each of `posts`, in the same order.
+/// Posts are expected newest first, as loaded; ties go to the newer post.
+pub fn collect_related(posts: &[Post], count: usize) -> Vec<Related<'_>> {
+    let tags: Vec<HashSet<String>> = posts
+        .iter()
+        .map(|post| post.tags.iter().map(|tag| markdown::slugify(tag)).collect())
+        .collect();
+    let vectors = tf_idf(posts);
+    let dated: Vec<usize> = (0..posts.len()).filter(|&i| posts[i].has_date()).collect();
+
+    (0..posts.len())
+        .map(|i| {
+            let mut scored: Vec<(f64, usize)> = (0..posts.len())
+                .filter(|&j| j != i)
+                .map(|j| {
+                    let shared = tags[i].intersection(&tags[j]).count() as f64;
+                    let score =
+                        shared * TAG_WEIGHT + cosine(&vectors[i], &vectors[j]) * TEXT_WEIGHT;
+                    (score, j)
+                })
+                .filter(|&(score, _)| score > 0.0)
+                .collect();
+            scored.sort_by(|(a, i), (b, j)| {
+                b.total_cmp(a)
+                    .then_with(|| posts[*j].date.cmp(&posts[*i].date))
+                    .then_with(|| posts[*i].slug.cmp(&posts[*j].slug))
+            });
+
+            let position = dated.iter().position(|&d| d == i);
+            Related {
+                posts: scored.iter().take(count).map(|&(_, j)| &posts[j]).collect(),
+                newer: position
+                    .and_then(|p| p.checked_sub(1))
+                    .map(|p| &posts[dated[p]]),
+                older: position.and_then(|p| dated.get(p + 1)).map(|&d| &posts[d]),
+            }
+        })
+        .collect()
+}
+
+/// Each post's title and body as a unit-length vector of term weights:
+/// log-scaled term frequency times inverse document frequency
+fn tf_idf(posts: &[Post]) -> Vec<HashMap<String, f64>> {
+    let counts: Vec<HashMap<String, u32>> = posts
+        .iter()
+        .map(|post| {
+            let text = format!("{} {}", post.title, search::plain_text(&post.content_html));
+            let mut counts = HashMap::new();
+            for term in search::terms_of(&text) {
+                *counts.entry(term).or_default() += 1;
+            }
+            counts
+        })
+        .collect();
+
+    let mut document_frequency: HashMap<&str, u32> = HashMap::new();
+    for terms in &counts {
+        for term in terms.keys() {
+            *document_frequency.entry(term).or_default() += 1;
+        }
+    }
+
+    let total = posts.len() as f64;
+    counts
+        .iter()
+        .map(|terms| {
+            let mut vector: HashMap<String, f64> = terms
+                .iter()
+                .map(|(term, &count)| {
+                    let idf = (total / document_frequency[term.as_str()] as f64).ln();
+                    (term.clone(), (1.0 + (count as f64).ln()) * idf)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect();
+            let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                vector.values_mut().for_each(|w| *w /= norm);
+            }
+            vector
+        })
+        .collect()
+}
+
+fn cosine(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    small
+        .iter()
+        .filter_map(|(term, weight)| Some(weight * large.get(term)?))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_related_by_tags_text_and_recency() {
+        let posts = vec![
+            Post::for_test(
+                "newest",
+                "date: 2024-04-01\ntags: [rust]",
+                "Parsing frontmatter",
+            ),
+            Post::for_test(
+                "zigbee",
+                "date: 2024-03-01\ntags: [home]",
+                "Zigbee lights and Kubernetes",
+            ),
+            Post::for_test(
+                "older-rust",
+                "date: 2024-02-01\ntags: [rust]",
+                "Borrow checker notes",
+            ),
+            Post::for_test(
+                "lights",
+                "date: 2024-01-01",
+                "More Zigbee lights on Kubernetes",
+            ),
+            Post::for_test("oldest-rust", "date: 2023-01-01\ntags: [rust]", "Lifetimes"),
+        ];
+        let related = collect_related(&posts, 2);
+
+        let slugs = |r: &Related| r.posts.iter().map(|p| p.slug.clone()).collect::<Vec<_>>();
+        // Equal scores from one shared tag; the newer post wins
+        assert_eq!(slugs(&related[0]), ["older-rust", "oldest-rust"]);
+        // No shared tags, but the same words
+        assert_eq!(slugs(&related[1]), ["lights"]);
+
+        assert!(related[0].newer.is_none());
+        assert_eq!(related[0].older.map(|p| p.slug.as_str()), Some("zigbee"));
+        assert_eq!(related[2].newer.map(|p| p.slug.as_str()), Some("zigbee"));
+        assert!(related[4].older.is_none());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::build::SitePaths;
+use crate::markdown;
+use chrono::Local;
+use std::fs;
+use std::path::PathBuf;
+
+/// Writes a new markdown file with frontmatter filled in, refusing to
+/// overwrite an existing one. Returns the path of the created file.
+pub fn new_content(
+    paths: &SitePaths,
+    title: &str,
+    slug: Option<&str>,
+    page: bool,
+) -> Result<PathBuf, String> {
+    let slug = match slug {
+        Some(slug) => markdown::slugify(slug),
+        None => markdown::slugify(title),
+    };
+    if slug.is_empty() {
+        return Err("Cannot derive a slug from the title; pass --slug".to_string());
+    }
+
+    let (dir, frontmatter) = if page {
+        (
+            paths.pages_dir(),
+            format!("title: {}\ndescription: \"\"\n", yaml_string(title)),
+        )
+    } else {
+        (
+            paths.posts_dir(),
+            format!(
+                "title: {}\ndate: {}\ntags: []\n",
+                yaml_string(title),
+                Local::now().format("%Y-%m-%d")
+            ),
+        )
+    };
+
+    let path = dir.join(format!("{}.md", slug));
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+
+    fs::create_dir_all(&dir).map_err(|e| format!("IO error: {}", e))?;
+    fs::write(&path, format!("---\n{}---\n\n", frontmatter))
+        .map_err(|e| format!("IO error: {}", e))?;
+    Ok(path)
+}
+
+/// Quotes a string as a double-quoted YAML scalar
+fn yaml_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,292 @@
+//! Builds the inverted index behind the client-side search page. Words are
+//! normalized and stemmed here and again by the page's script, which reads
+//! the stop words and letter folding from the index so the two agree.
+
+use crate::markdown;
+use crate::post::Post;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Written to the site root and fetched the first time the search box is used
+pub const INDEX_FILE: &str = "search-index.json";
+
+/// How much a word counts towards a post's score, by where it appears
+const TITLE_WEIGHT: u32 = 8;
+const TAG_WEIGHT: u32 = 4;
+const EXCERPT_WEIGHT: u32 = 2;
+const BODY_WEIGHT: u32 = 1;
+
+/// Words too common to narrow a search; left out to keep the index small
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "i",
+    "if", "in", "into", "is", "it", "its", "me", "my", "of", "on", "or", "our", "so", "than",
+    "that", "the", "their", "them", "then", "there", "these", "they", "this", "to", "was", "we",
+    "were", "what", "when", "which", "will", "with", "you", "your",
+];
+
+/// Accented Latin letters folded to their plain spelling
+const FOLDS: &[(char, &str)] = &[
+    ('à', "a"),
+    ('á', "a"),
+    ('â', "a"),
+    ('ã', "a"),
+    ('ä', "a"),
+    ('å', "a"),
+    ('æ', "ae"),
+    ('ç', "c"),
+    ('è', "e"),
+    ('é', "e"),
+    ('ê', "e"),
+    ('ë', "e"),
+    ('ì', "i"),
+    ('í', "i"),
+    ('î', "i"),
+    ('ï', "i"),
+    ('ñ', "n"),
+    ('ò', "o"),
+    ('ó', "o"),
+    ('ô', "o"),
+    ('õ', "o"),
+    ('ö', "o"),
+    ('ø', "o"),
+    ('œ', "oe"),
+    ('ß', "ss"),
+    ('ù', "u"),
+    ('ú', "u"),
+    ('û', "u"),
+    ('ü', "u"),
+    ('ý', "y"),
+    ('ÿ', "y"),
+];
+
+/// Elements whose text isn't prose: code, rendered math, the table of
+/// contents (a copy of the headings) and inline icons
+static SKIPPED_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"^<(pre|script|style|nav|svg|button)\b|^<(\w+)[^>]*\sclass="[^"]*\b(code-block|katex)\b"#,
+    )
+    .unwrap()
+});
+static TAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(/?)(\w+)[^>]*?(/?)>").unwrap());
+static ENTITY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"&(#?\w+);").unwrap());
+
+#[derive(Serialize)]
+pub struct SearchIndex {
+    docs: Vec<SearchDoc>,
+    /// Stemmed word to a flat list of `[doc, weight, doc, weight, ...]`
+    terms: BTreeMap<String, Vec<u32>>,
+    stop_words: &'static [&'static str],
+    folds: BTreeMap<char, &'static str>,
+}
+
+/// What a search result shows for a post
+#[derive(Serialize)]
+struct SearchDoc {
+    url: String,
+    title: String,
+    date: String,
+    excerpt: String,
+    /// Tag names with their slugs, for linking to tag pages
+    tags: Vec<(String, String)>,
+    word_count: usize,
+    reading_minutes: u32,
+}
+
+/// Indexes each post's title, tags, excerpt and body text
+pub fn build_index(posts: &[Post]) -> SearchIndex {
+    let mut terms: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    let mut docs = Vec::with_capacity(posts.len());
+
+    for (i, post) in posts.iter().enumerate() {
+        let mut weights: HashMap<String, u32> = HashMap::new();
+        let fields = [
+            (post.title.as_str(), TITLE_WEIGHT),
+            (&post.tags.join(" "), TAG_WEIGHT),
+            (&post.excerpt, EXCERPT_WEIGHT),
+            (&plain_text(&post.content_html), BODY_WEIGHT),
+        ];
+        for (text, weight) in fields {
+            for term in terms_of(text) {
+                *weights.entry(term).or_default() += weight;
+            }
+        }
+        for (term, weight) in weights {
+            terms.entry(term).or_default().extend([i as u32, weight]);
+        }
+
+        docs.push(SearchDoc {
+            url: post.url_path(),
+            title: post.title.clone(),
+            date: post.formatted_date(),
+            excerpt: post.excerpt.clone(),
+            tags: post
+                .tags
+                .iter()
+                .map(|tag| (tag.clone(), markdown::slugify(tag)))
+                .collect(),
+            word_count: post.word_count,
+            reading_minutes: post.reading_minutes,
+        });
+    }
+
+    // Postings were added in map order; sort them so the index is stable
+    for postings in terms.values_mut() {
+        let mut pairs: Vec<[u32; 2]> = postings.chunks(2).map(|p| [p[0], p[1]]).collect();
+        pairs.sort_unstable();
+        *postings = pairs.concat();
+    }
+
+    SearchIndex {
+        docs,
+        terms,
+        stop_words: STOP_WORDS,
+        folds: FOLDS.iter().copied().collect(),
+    }
+}
+
+/// Normalized, stemmed words of `text`, without stop words
+pub fn terms_of(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(normalize)
+        .filter(|word| word.chars().count() > 1 && !STOP_WORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+}
+
+/// Lowercases a word and folds accented letters
+fn normalize(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match FOLDS.iter().find(|(from, _)| *from == c) {
+            Some((_, to)) => to.to_string(),
+            None => c.to_string(),
+        })
+        .collect()
+}
+
+/// A light suffix-stripping stemmer, so "caching" and "cached" both become
+/// "cach". The search page's script implements the same rules.
+fn stem(word: &str) -> String {
+    const SUFFIXES: [(&str, &str); 6] = [
+        ("ies", "y"),
+        ("sses", "ss"),
+        ("ing", ""),
+        ("ed", ""),
+        ("ly", ""),
+        ("s", ""),
+    ];
+    if word.chars().count() <= 3 {
+        return word.to_string();
+    }
+    for (suffix, replacement) in SUFFIXES {
+        let Some(base) = word.strip_suffix(suffix) else {
+            continue;
+        };
+        if base.chars().count() < 3 {
+            continue;
+        }
+        if suffix == "s" && (base.ends_with('s') || base.ends_with('u') || base.ends_with('i')) {
+            return word.to_string();
+        }
+        let mut stem = format!("{}{}", base, replacement);
+        // "running" -> "runn" -> "run"
+        if suffix == "ing" || suffix == "ed" {
+            let mut chars = stem.chars().rev();
+            if let (Some(last), Some(before)) = (chars.next(), chars.next()) {
+                if last == before && !"aeiouylsz".contains(last) {
+                    stem.pop();
+                }
+            }
+        }
+        return stem;
+    }
+    word.to_string()
+}
+
+/// Visible prose of rendered post HTML, skipping code and math
+pub fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut last = 0;
+    // Name and nesting depth of the element being skipped
+    let mut skipping: Option<(String, usize)> = None;
+
+    for caps in TAG_RE.captures_iter(html) {
+        let tag = caps.get(0).unwrap();
+        if skipping.is_none() {
+            text.push_str(&unescape(&html[last..tag.start()]));
+            text.push(' ');
+        }
+        last = tag.end();
+
+        let closing = !caps[1].is_empty();
+        let self_closing = !caps[3].is_empty();
+        let name = caps[2].to_ascii_lowercase();
+        match &mut skipping {
+            Some((skipped, depth)) if *skipped == name && !self_closing => {
+                if closing {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        skipping = None;
+                    }
+                } else {
+                    *depth += 1;
+                }
+            }
+            Some(_) => {}
+            None if !closing && !self_closing && SKIPPED_RE.is_match(tag.as_str()) => {
+                skipping = Some((name, 1));
+            }
+            None => {}
+        }
+    }
+    if skipping.is_none() {
+        text.push_str(&unescape(&html[last..]));
+    }
+    text
+}
+
+fn unescape(text: &str) -> std::borrow::Cow<'_, str> {
+    ENTITY_RE.replace_all(text, |caps: &regex::Captures| {
+        let entity = &caps[1];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        decoded.map_or_else(|| caps[0].to_string(), String::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terms_are_normalized_and_stemmed() {
+        let terms: Vec<String> =
+            terms_of("The Café's caching, cached caches & running queries").collect();
+        assert_eq!(terms, ["cafe", "cach", "cach", "cache", "run", "query"]);
+    }
+
+    #[test]
+    fn test_plain_text_skips_code_and_math() {
+        let html = concat!(
+            r##"<nav class="toc"><a href="#a">Intro</a></nav><h2 id="a">Intro</h2>"##,
+            r#"<p>Keys &amp; <code>certs</code> <span class="katex"><span class="katex-mathml">x</span></span>done</p>"#,
+            r#"<div class="code-block"><div class="code-block-header">sh</div><pre><code>ssh-keygen</code></pre></div>"#,
+        );
+        let text = plain_text(html);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        assert_eq!(words, ["Intro", "Keys", "&", "certs", "done"]);
+    }
+}
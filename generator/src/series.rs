@@ -0,0 +1,173 @@
+use crate::error::{Error, ErrorKind};
+use crate::markdown;
+use crate::post::Post;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Posts that share a `series`, in reading order
+pub struct Series<'a> {
+    pub name: String,
+    pub slug: String,
+    pub parts: Vec<&'a Post>,
+}
+
+impl Series<'_> {
+    /// One-based position of `post` in the series
+    pub fn part_number(&self, post: &Post) -> usize {
+        self.parts
+            .iter()
+            .position(|p| p.slug == post.slug)
+            .map_or(0, |i| i + 1)
+    }
+
+    pub fn count(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Titles and URLs of the parts, which every part's page lists, so a
+    /// post page is re-rendered when another part changes
+    pub fn fingerprint(&self) -> String {
+        let parts: Vec<String> = self
+            .parts
+            .iter()
+            .map(|part| format!("{}\t{}", part.slug, part.title))
+            .collect();
+        format!("{}\n{}", self.name, parts.join("\n"))
+    }
+}
+
+/// Groups posts by series, ordered by `series_order` and then by date.
+/// Series names that slugify to the same URL are merged like tags.
+pub fn collect_series(posts: &[Post]) -> Vec<Series<'_>> {
+    let mut series: BTreeMap<String, Series> = BTreeMap::new();
+
+    for post in posts {
+        let Some(name) = &post.series else {
+            continue;
+        };
+        let slug = markdown::slugify(name);
+        series
+            .entry(slug.clone())
+            .or_insert_with(|| Series {
+                name: name.clone(),
+                slug,
+                parts: Vec::new(),
+            })
+            .parts
+            .push(post);
+    }
+
+    let mut series: Vec<Series> = series.into_values().collect();
+    for s in &mut series {
+        s.parts.sort_by(|a, b| {
+            let key = |p: &Post| (p.series_order.unwrap_or(u32::MAX), p.date);
+            key(a).cmp(&key(b)).then_with(|| a.slug.cmp(&b.slug))
+        });
+    }
+    series
+}
+
+/// Warnings for series whose numbering has gaps, repeats or is only given
+/// for some parts. `sources` maps post URLs to their markdown files.
+pub fn check(series: &[Series], sources: &HashMap<String, PathBuf>) -> Vec<Error> {
+    let mut warnings = Vec::new();
+
+    for s in series {
+        let numbered: Vec<(u32, &Post)> = s
+            .parts
+            .iter()
+            .filter_map(|part| Some((part.series_order?, *part)))
+            .collect();
+        if numbered.is_empty() {
+            continue;
+        }
+
+        for part in s.parts.iter().filter(|part| part.series_order.is_none()) {
+            let message = format!(
+                "`{}` has no `series_order`, but other parts of series `{}` do",
+                part.slug, s.name
+            );
+            warnings.push(warning(part, "series", message, sources));
+        }
+
+        for pair in numbered.windows(2) {
+            let ((order, first), (next, second)) = (pair[0], pair[1]);
+            if order == next {
+                let message = format!(
+                    "`{}` and `{}` are both part {} of series `{}`",
+                    first.slug, second.slug, order, s.name
+                );
+                warnings.push(warning(second, "series_order", message, sources));
+            }
+        }
+
+        let last = numbered.last().map_or(0, |(order, _)| *order);
+        let missing: Vec<u32> = (1..last)
+            .filter(|n| !numbered.iter().any(|(order, _)| order == n))
+            .collect();
+        if let Some(&gap) = missing.first() {
+            let numbers: Vec<String> = missing.iter().map(u32::to_string).collect();
+            let message = format!(
+                "series `{}` is missing {} {}",
+                s.name,
+                if missing.len() == 1 { "part" } else { "parts" },
+                numbers.join(", ")
+            );
+            // Point at the first part after the gap
+            let (_, part) = numbered
+                .iter()
+                .find(|(order, _)| *order > gap)
+                .copied()
+                .unwrap_or(numbered[0]);
+            warnings.push(warning(part, "series_order", message, sources));
+        }
+    }
+
+    warnings
+}
+
+/// A warning pointing at `key` in the post's frontmatter
+fn warning(post: &Post, key: &str, message: String, sources: &HashMap<String, PathBuf>) -> Error {
+    let error = Error::new(ErrorKind::Series, message).warning();
+    let Some(path) = sources.get(&post.url_path()) else {
+        return error;
+    };
+    let source = fs::read_to_string(path).unwrap_or_default();
+    match markdown::value_offset(&source, key) {
+        Some(offset) => error.at_offset(&source, offset).with_path(path),
+        None => error.with_path(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_series_orders_parts_and_warns() {
+        let posts = vec![
+            Post::for_test("d", "series: Home Lab\nseries_order: 4", "Body"),
+            Post::for_test("b", "series: home-lab\nseries_order: 2", "Body"),
+            Post::for_test("c", "series: Home Lab\nseries_order: 2", "Body"),
+            Post::for_test("e", "series: Other", "Body"),
+        ];
+        let series = collect_series(&posts);
+
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].name, "Home Lab");
+        let slugs: Vec<&str> = series[0].parts.iter().map(|p| p.slug.as_str()).collect();
+        assert_eq!(slugs, ["b", "c", "d"]);
+        assert_eq!(series[0].part_number(&posts[0]), 3);
+
+        let warnings = check(&series, &HashMap::new());
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "`b` and `c` are both part 2 of series `Home Lab`",
+                "series `Home Lab` is missing parts 1, 3",
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Response, Server};
+
+/// Endpoint polled by the live-reload client in `live_reload.html`
+const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+/// Latest build status, shared between the file watcher and the HTTP server
+pub struct LiveReload {
+    /// Distinguishes server processes so a restart also triggers a reload
+    session: u128,
+    generation: AtomicU64,
+    errors: Mutex<Vec<String>>,
+}
+
+impl LiveReload {
+    pub fn new() -> Self {
+        Self {
+            session: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default(),
+            generation: AtomicU64::new(0),
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a finished build; connected browsers reload on the next poll
+    pub fn publish(&self, errors: Vec<String>) {
+        *self.errors.lock().unwrap() = errors;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn status_json(&self) -> String {
+        let errors = self.errors.lock().unwrap();
+        serde_json::json!({
+            "build": format!("{}-{}", self.session, self.generation.load(Ordering::SeqCst)),
+            "errors": *errors,
+        })
+        .to_string()
+    }
+}
+
+/// Serves the output directory over HTTP until the process is killed
+pub fn serve(output_dir: &Path, host: &str, port: u16, reload: &LiveReload) -> Result<(), String> {
+    let addr = format!("{}:{}", host, port);
+    let server = Server::http(&addr).map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    println!("Serving {} at http://{}/", output_dir.display(), addr);
+
+    for request in server.incoming_requests() {
+        if request.url().split('?').next() == Some(LIVE_RELOAD_PATH) {
+            let response = Response::from_string(reload.status_json())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+                .with_header(Header::from_bytes("Cache-Control", "no-store").unwrap());
+            let _ = request.respond(response);
+            continue;
+        }
+
+        let response = match resolve(output_dir, request.url()) {
+            Some(path) => match fs::read(&path) {
+                Ok(body) => Response::from_data(body)
+                    .with_header(content_type(&path))
+                    .with_status_code(200),
+                Err(_) => not_found(),
+            },
+            None => not_found(),
+        };
+        let _ = request.respond(response);
+    }
+
+    Ok(())
+}
+
+/// Maps a request URL onto a file in the output directory, refusing paths
+/// that would escape it
+fn resolve(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(&percent_decode(path)).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if resolved.is_dir() {
+        resolved.push("index.html");
+    }
+    resolved.is_file().then_some(resolved)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn content_type(path: &Path) -> Header {
+    let mime = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("pdf") => "application/pdf",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    };
+    Header::from_bytes("Content-Type", mime).unwrap()
+}
+
+fn not_found() -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string("404 Not Found").with_status_code(404)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_stays_inside_root() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(resolve(root, "/src/main.rs?x=1"), Some(root.join("src/main.rs")));
+        assert_eq!(resolve(root, "/../generator/src/main.rs"), None);
+        assert_eq!(resolve(root, "/src/%2e%2e/Cargo.toml"), None);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::post::Post;
+use chrono::NaiveDate;
+
+/// A page listed in sitemap.xml
+pub struct SitemapEntry {
+    /// Site-relative URL of the page
+    pub path: String,
+    pub lastmod: Option<NaiveDate>,
+}
+
+impl SitemapEntry {
+    /// W3C date for `<lastmod>`
+    pub fn formatted_lastmod(&self) -> String {
+        self.lastmod
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Every page the build renders, collected as outputs are scheduled so new
+/// kinds of page show up in the sitemap without extra wiring
+#[derive(Default)]
+pub struct Sitemap {
+    pub entries: Vec<SitemapEntry>,
+}
+
+impl Sitemap {
+    pub fn add(&mut self, path: String, lastmod: Option<NaiveDate>) {
+        self.entries.push(SitemapEntry { path, lastmod });
+    }
+}
+
+/// Last modification of a page listing several posts: the newest of theirs
+pub fn newest<'a>(posts: impl IntoIterator<Item = &'a Post>) -> Option<NaiveDate> {
+    posts.into_iter().filter_map(Post::last_modified).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newest_prefers_updated() {
+        let posts = [
+            Post::for_test("a", "date: 2023-01-01\nupdated: 2024-06-01", "Body"),
+            Post::for_test("b", "date: 2024-02-01", "Body"),
+            Post::for_test("c", "", "Body"),
+        ];
+
+        assert_eq!(newest(&posts), NaiveDate::from_ymd_opt(2024, 6, 1));
+        assert_eq!(newest(&posts[2..]), None);
+    }
+}
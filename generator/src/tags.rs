@@ -0,0 +1,58 @@
+use crate::markdown;
+use crate::post::Post;
+use std::collections::BTreeMap;
+
+/// A tag and the posts filed under it
+pub struct Tag<'a> {
+    pub name: String,
+    pub slug: String,
+    pub posts: Vec<&'a Post>,
+}
+
+impl Tag<'_> {
+    pub fn count(&self) -> usize {
+        self.posts.len()
+    }
+}
+
+/// Groups posts by tag, keeping each tag's posts in the order given.
+/// Tags that slugify to the same URL are merged under the first spelling seen.
+pub fn collect_tags(posts: &[Post]) -> Vec<Tag<'_>> {
+    let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
+
+    for post in posts {
+        for name in &post.tags {
+            let slug = markdown::slugify(name);
+            let tag = tags.entry(slug.clone()).or_insert_with(|| Tag {
+                name: name.clone(),
+                slug,
+                posts: Vec::new(),
+            });
+            if !tag.posts.iter().any(|p| std::ptr::eq(*p, post)) {
+                tag.posts.push(post);
+            }
+        }
+    }
+
+    tags.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_tags_merges_by_slug() {
+        let posts = vec![
+            Post::for_test("a", "tags: [Self Hosting, rust]", "Body"),
+            Post::for_test("b", "tags: [self-hosting]", "Body"),
+        ];
+        let tags = collect_tags(&posts);
+
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].slug, "rust");
+        assert_eq!(tags[1].slug, "self-hosting");
+        assert_eq!(tags[1].name, "Self Hosting");
+        assert_eq!(tags[1].count(), 2);
+    }
+}
@@ -1,13 +1,40 @@
 use askama::Template;
+use crate::archive::{Archive, Year};
 use crate::config::SiteConfig;
+use crate::meta::Meta;
+use crate::page::Page;
+use crate::paginate::Pager;
 use crate::post::Post;
+use crate::related::Related;
+use crate::series::Series;
+use crate::sitemap::{Sitemap, SitemapEntry};
+use crate::tags::Tag;
+use chrono::NaiveDate;
+
+mod filters {
+    use crate::markdown;
+
+    /// Slugifies a value the same way post and tag URLs are built
+    pub fn slugify<T: std::fmt::Display>(s: T) -> askama::Result<String> {
+        Ok(markdown::slugify(&s.to_string()))
+    }
+}
+
+/// Relative path from the page at a site-relative URL (e.g. `tags/rust/`)
+/// back to the site root, so output works from any base path
+fn path_prefix(url_path: &str) -> String {
+    "../".repeat(url_path.split('/').filter(|s| !s.is_empty()).count())
+}
 
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate<'a> {
     pub site: &'a SiteConfig,
-    pub posts: &'a [Post],
-    pub path_prefix: &'static str,
+    pub pager: &'a Pager<'a, Post>,
+    /// Years with posts, for the archive links below the list
+    pub years: &'a [Year<'a>],
+    pub path_prefix: &'a str,
+    pub meta: Meta,
 }
 
 #[derive(Template)]
@@ -15,44 +42,320 @@ pub struct IndexTemplate<'a> {
 pub struct PostTemplate<'a> {
     pub site: &'a SiteConfig,
     pub post: &'a Post,
-    pub path_prefix: &'static str,
+    /// The series the post is part of, for the series navigation box
+    pub series: Option<&'a Series<'a>>,
+    /// Suggested posts and the neighbouring posts by date
+    pub related: &'a Related<'a>,
+    /// The build's date, for the scheduled banner
+    pub today: NaiveDate,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
 }
 
 #[derive(Template)]
 #[template(path = "page.html")]
 pub struct PageTemplate<'a> {
     pub site: &'a SiteConfig,
-    pub page: &'a Post,
-    pub path_prefix: &'static str,
+    pub page: &'a Page,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
 }
 
-/// Renders the index page with a list of posts
-pub fn render_index(site: &SiteConfig, posts: &[Post]) -> String {
+/// Renders one page of the post list on the index
+pub fn render_index<'a>(
+    site: &SiteConfig,
+    pager: &Pager<'a, Post>,
+    years: &[Year<'a>],
+) -> askama::Result<String> {
     let template = IndexTemplate {
         site,
-        posts,
-        path_prefix: "",
+        pager,
+        years,
+        path_prefix: &path_prefix(&pager.url_path()),
+        meta: match pager.number {
+            1 => Meta::home(site, "", site.name.clone()),
+            n => Meta::website(
+                site,
+                &pager.url_path(),
+                format!("Page {} — {}", n, site.name),
+                site.description.clone(),
+            ),
+        },
     };
-    template.render().expect("Failed to render index template")
+    template.render()
 }
 
 /// Renders a single post page
-pub fn render_post(site: &SiteConfig, post: &Post) -> String {
+pub fn render_post(
+    site: &SiteConfig,
+    post: &Post,
+    series: Option<&Series>,
+    related: &Related,
+    today: NaiveDate,
+) -> askama::Result<String> {
     let template = PostTemplate {
         site,
         post,
-        path_prefix: "../../",
+        series,
+        related,
+        today,
+        path_prefix: &path_prefix(&post.url_path()),
+        meta: Meta::post(site, post),
     };
-    template.render().expect("Failed to render post template")
+    template.render()
 }
 
-/// Renders a static page (uses Post type with optional date)
-pub fn render_page(site: &SiteConfig, page: &Post) -> String {
+/// Renders a static page
+pub fn render_page(site: &SiteConfig, page: &Page) -> askama::Result<String> {
     let template = PageTemplate {
         site,
         page,
-        path_prefix: "../",
+        path_prefix: &path_prefix(&page.url_path()),
+        meta: Meta::page(site, page),
+    };
+    template.render()
+}
+
+
+#[derive(Template)]
+#[template(path = "tags.html")]
+pub struct TagsTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub tags: &'a [Tag<'a>],
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "tag.html")]
+pub struct TagTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub tag: &'a Tag<'a>,
+    pub pager: &'a Pager<'a, &'a Post>,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "series.html")]
+pub struct SeriesTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub series: &'a Series<'a>,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "archive.html")]
+pub struct ArchiveTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub archive: &'a Archive<'a>,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "year.html")]
+pub struct YearTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub year: &'a Year<'a>,
+    /// Neighbouring years with posts, for the links at the bottom
+    pub newer: Option<i32>,
+    pub older: Option<i32>,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct SearchTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub path_prefix: &'a str,
+    pub meta: Meta,
+}
+
+#[derive(Template)]
+#[template(path = "atom.xml")]
+pub struct AtomTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub posts: Vec<&'a Post>,
+    pub updated: String,
+}
+
+#[derive(Template)]
+#[template(path = "rss.xml")]
+pub struct RssTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub posts: Vec<&'a Post>,
+    pub last_build_date: String,
+}
+
+/// Renders the list of all tags with their post counts
+pub fn render_tags(site: &SiteConfig, tags: &[Tag]) -> askama::Result<String> {
+    let template = TagsTemplate {
+        site,
+        tags,
+        path_prefix: &path_prefix("tags/"),
+        meta: Meta::website(
+            site,
+            "tags/",
+            format!("Tags — {}", site.name),
+            site.description.clone(),
+        ),
+    };
+    template.render()
+}
+
+/// Renders one page of the archive for a single tag
+pub fn render_tag<'a>(
+    site: &SiteConfig,
+    tag: &Tag,
+    pager: &Pager<'a, &'a Post>,
+) -> askama::Result<String> {
+    let template = TagTemplate {
+        site,
+        tag,
+        pager,
+        path_prefix: &path_prefix(&pager.url_path()),
+        meta: Meta::website(
+            site,
+            &pager.url_path(),
+            format!("{} — {}", tag.name, site.name),
+            format!("Posts tagged “{}”", tag.name),
+        ),
+    };
+    template.render()
+}
+
+/// Renders the landing page listing every part of a series
+pub fn render_series(site: &SiteConfig, series: &Series) -> askama::Result<String> {
+    let template = SeriesTemplate {
+        site,
+        series,
+        path_prefix: &path_prefix(&format!("series/{}/", series.slug)),
+        meta: Meta::website(
+            site,
+            &format!("series/{}/", series.slug),
+            format!("{} — {}", series.name, site.name),
+            format!("A {}-part series on {}", series.count(), site.name),
+        ),
+    };
+    template.render()
+}
+
+/// Renders the list of every post by year and month
+pub fn render_archive(site: &SiteConfig, archive: &Archive) -> askama::Result<String> {
+    let template = ArchiveTemplate {
+        site,
+        archive,
+        path_prefix: &path_prefix("archive/"),
+        meta: Meta::website(
+            site,
+            "archive/",
+            format!("Archive — {}", site.name),
+            format!("Every post on {}, by month", site.name),
+        ),
+    };
+    template.render()
+}
+
+/// Renders the posts from the year at `index` in `years`, newest first
+pub fn render_year(site: &SiteConfig, years: &[Year], index: usize) -> askama::Result<String> {
+    let year = &years[index];
+    let template = YearTemplate {
+        site,
+        year,
+        newer: index.checked_sub(1).map(|i| years[i].year),
+        older: years.get(index + 1).map(|y| y.year),
+        path_prefix: &path_prefix(&year.url_path()),
+        meta: Meta::website(
+            site,
+            &year.url_path(),
+            format!("{} — {}", year.year, site.name),
+            format!("Posts from {} on {}", year.year, site.name),
+        ),
+    };
+    template.render()
+}
+
+/// Renders the search page, which loads the search index on first use
+pub fn render_search(site: &SiteConfig) -> askama::Result<String> {
+    let template = SearchTemplate {
+        site,
+        path_prefix: &path_prefix("search/"),
+        meta: Meta::website(
+            site,
+            "search/",
+            format!("Search — {}", site.name),
+            format!("Search posts on {}", site.name),
+        ),
+    };
+    template.render()
+}
+
+/// Posts that belong in feeds. Feed entries require a timestamp, so undated
+/// posts are skipped rather than given a made-up date.
+fn feed_posts(posts: &[Post]) -> Vec<&Post> {
+    posts.iter().filter(|post| post.has_date()).collect()
+}
+
+/// Renders an Atom feed from posts sorted newest first
+pub fn render_atom(site: &SiteConfig, posts: &[Post]) -> askama::Result<String> {
+    let posts = feed_posts(posts);
+    let updated = posts
+        .iter()
+        .filter_map(|p| p.modified())
+        .max()
+        .map(|d| d.to_rfc3339())
+        .unwrap_or_default();
+    let template = AtomTemplate {
+        site,
+        posts,
+        updated,
+    };
+    template.render()
+}
+
+/// Renders an RSS 2.0 feed from posts sorted newest first
+pub fn render_rss(site: &SiteConfig, posts: &[Post]) -> askama::Result<String> {
+    let posts = feed_posts(posts);
+    let last_build_date = posts
+        .iter()
+        .filter_map(|p| p.modified())
+        .max()
+        .map(|d| d.to_rfc2822())
+        .unwrap_or_default();
+    let template = RssTemplate {
+        site,
+        posts,
+        last_build_date,
+    };
+    template.render()
+}
+
+#[derive(Template)]
+#[template(path = "sitemap.xml")]
+pub struct SitemapTemplate<'a> {
+    pub site: &'a SiteConfig,
+    pub entries: &'a [SitemapEntry],
+}
+
+#[derive(Template)]
+#[template(path = "robots.txt")]
+pub struct RobotsTemplate<'a> {
+    pub site: &'a SiteConfig,
+}
+
+/// Renders sitemap.xml with absolute URLs
+pub fn render_sitemap(site: &SiteConfig, sitemap: &Sitemap) -> askama::Result<String> {
+    let template = SitemapTemplate {
+        site,
+        entries: &sitemap.entries,
     };
-    template.render().expect("Failed to render page template")
+    template.render()
 }
 
+/// Renders robots.txt from the configured rules
+pub fn render_robots(site: &SiteConfig) -> askama::Result<String> {
+    RobotsTemplate { site }.render()
+}
@@ -0,0 +1,136 @@
+use crate::serve::LiveReload;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a burst of file events (e.g. an editor's atomic save)
+/// to settle before rebuilding
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Templates are compiled into the binary, so they only exist on disk when
+/// running from a source checkout
+fn templates_dir() -> Option<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("templates");
+    dir.is_dir().then_some(dir)
+}
+
+/// Watches the content root (and templates, when available) on a background
+/// thread, calling `rebuild` after each change and publishing the result
+pub fn spawn<F>(
+    content_root: PathBuf,
+    output_dir: PathBuf,
+    rebuild: F,
+    reload: Arc<LiveReload>,
+) -> Result<(), String>
+where
+    F: Fn() -> Vec<String> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())
+        .map_err(|e| format!("Failed to start file watcher: {}", e))?;
+
+    watcher
+        .watch(&content_root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", content_root.display(), e))?;
+    println!("Watching {}", content_root.display());
+
+    let templates = templates_dir();
+    if let Some(dir) = &templates {
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+        println!("Watching {}", dir.display());
+    }
+
+    thread::spawn(move || {
+        // Keep the watcher alive for as long as the thread runs
+        let _watcher = watcher;
+
+        while let Ok(event) = rx.recv() {
+            let mut changed: Vec<PathBuf> = Vec::new();
+            collect_paths(event, &mut changed);
+            thread::sleep(DEBOUNCE);
+            while let Ok(event) = rx.try_recv() {
+                collect_paths(event, &mut changed);
+            }
+
+            // Our own writes must not retrigger a build
+            changed.retain(|path| !path.starts_with(&output_dir));
+            if changed.is_empty() {
+                continue;
+            }
+
+            let template_changed = templates
+                .as_ref()
+                .is_some_and(|dir| changed.iter().any(|path| path.starts_with(dir)));
+            if template_changed {
+                // Only returns if the new binary failed to build or start
+                reload.publish(vec![restart_with_new_templates()]);
+                continue;
+            }
+
+            let started = Instant::now();
+            let errors = rebuild();
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            println!("Rebuilt in {:.0?}", started.elapsed());
+            reload.publish(errors);
+        }
+    });
+
+    Ok(())
+}
+
+fn collect_paths(event: notify::Result<notify::Event>, changed: &mut Vec<PathBuf>) {
+    if let Ok(event) = event {
+        if !event.kind.is_access() {
+            changed.extend(event.paths);
+        }
+    }
+}
+
+/// Recompiles the generator so template edits take effect, then replaces the
+/// running process with the new binary. Returns an error message on failure.
+fn restart_with_new_templates() -> String {
+    println!("Templates changed, recompiling generator...");
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let mut cargo = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()));
+    cargo.arg("build").arg("--manifest-path").arg(&manifest);
+    if !cfg!(debug_assertions) {
+        cargo.arg("--release");
+    }
+
+    match cargo.output() {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            eprintln!("{}", stderr);
+            return format!("Failed to recompile templates:\n{}", stderr);
+        }
+        Err(e) => return format!("Failed to run cargo to recompile templates: {}", e),
+    }
+
+    exec_self()
+}
+
+#[cfg(unix)]
+fn exec_self() -> String {
+    use std::os::unix::process::CommandExt;
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return format!("Failed to locate generator binary: {}", e),
+    };
+    let error = Command::new(exe).args(std::env::args_os().skip(1)).exec();
+    format!("Failed to restart generator: {}", error)
+}
+
+#[cfg(not(unix))]
+fn exec_self() -> String {
+    "Templates were recompiled; restart `serve` to pick them up".to_string()
+}